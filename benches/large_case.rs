@@ -4,7 +4,10 @@ use std::time::Duration;
 use criterion::*;
 use serde::de::Deserialize;
 
-use pubgrub::{resolve, OfflineDependencyProvider, Package, Range, SemanticVersion, VersionSet};
+use pubgrub::{
+    resolve, Dependencies, DependencyProvider, OfflineDependencyProvider, Package, Range,
+    SemanticVersion, VersionSet,
+};
 
 fn bench<'a, P: Package + Deserialize<'a>, VS: VersionSet + Deserialize<'a>>(
     b: &mut Bencher,
@@ -13,16 +16,46 @@ fn bench<'a, P: Package + Deserialize<'a>, VS: VersionSet + Deserialize<'a>>(
     <VS as VersionSet>::V: Deserialize<'a>,
 {
     let dependency_provider: OfflineDependencyProvider<P, VS> = ron::de::from_str(case).unwrap();
+    bench_provider(b, &dependency_provider);
+}
 
+fn bench_provider<P: Package, VS: VersionSet>(
+    b: &mut Bencher,
+    dependency_provider: &OfflineDependencyProvider<P, VS>,
+) {
     b.iter(|| {
         for p in dependency_provider.packages() {
             for n in dependency_provider.versions(p).unwrap() {
-                let _ = resolve(&dependency_provider, p.clone(), n.clone());
+                let _ = resolve(dependency_provider, p.clone(), n.clone());
             }
         }
     });
 }
 
+/// The same registry with package names that are costlier to clone, hash and compare.
+fn with_string_names(
+    dependency_provider: &OfflineDependencyProvider<u16, Range<u32>>,
+) -> OfflineDependencyProvider<String, Range<u32>> {
+    let mut strings = OfflineDependencyProvider::new();
+    for p in dependency_provider.packages() {
+        for n in dependency_provider.versions(p).unwrap() {
+            let Ok(Dependencies::Available(dependencies)) =
+                dependency_provider.get_dependencies(p, n)
+            else {
+                continue;
+            };
+            strings.add_dependencies(
+                format!("package-{p}"),
+                *n,
+                dependencies
+                    .into_iter()
+                    .map(|(dependency, range)| (format!("package-{dependency}"), range)),
+            );
+        }
+    }
+    strings
+}
+
 fn bench_nested(c: &mut Criterion) {
     let mut group = c.benchmark_group("large_cases");
     group.measurement_time(Duration::from_secs(20));
@@ -32,9 +65,14 @@ fn bench_nested(c: &mut Criterion) {
         let name = case.file_name().unwrap().to_string_lossy();
         let data = std::fs::read_to_string(&case).unwrap();
         if name.ends_with("u16_NumberVersion.ron") || name.ends_with("u16_u32.ron") {
-            group.bench_function(name, |b| {
+            group.bench_function(name.clone(), |b| {
                 bench::<u16, Range<u32>>(b, &data);
             });
+            // Packages that are not `Copy` keep the cost of interning them measured.
+            let dependency_provider = with_string_names(&ron::de::from_str(&data).unwrap());
+            group.bench_function(format!("{name}_String"), |b| {
+                bench_provider(b, &dependency_provider);
+            });
         } else if name.ends_with("str_SemanticVersion.ron") {
             group.bench_function(name, |b| {
                 bench::<&str, Range<SemanticVersion>>(b, &data);
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MPL-2.0
+use std::cmp::Reverse;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use criterion::*;
+
+use pubgrub::{
+    resolve, Dependencies, Dependency, DependencyProvider, PackageResolutionStatistics, Ranges,
+};
+
+/// A registry where every version depends on a few packages
+/// and on any of many other packages, in version ranges that often conflict.
+///
+/// The alternatives and the conflicts make for many incompatibilities
+/// with a lot of terms, which unit propagation has to keep track of.
+/// Watching two terms of each of them pays off with many alternatives per dependency.
+struct ManyAlternatives {
+    versions: u32,
+    dependencies: Vec<Vec<Vec<Dependency<u32, Ranges<u32>>>>>,
+}
+
+impl ManyAlternatives {
+    fn new(packages: u32, versions: u32, requirements: u32, alternatives: u32) -> Self {
+        // A xorshift generator, so that the registry is the same for every run.
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move |n: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as u32
+        };
+        let range = |next: &mut dyn FnMut(u32) -> u32| {
+            let low = next(versions / 2);
+            if next(4) == 0 {
+                Ranges::between(low, low + 1 + next(versions / 2))
+            } else {
+                Ranges::higher_than(low)
+            }
+        };
+        let dependencies = (0..packages)
+            .map(|package| {
+                (0..versions)
+                    .map(|_| {
+                        let mut dependencies = Vec::new();
+                        if package + 1 == packages {
+                            return dependencies;
+                        }
+                        // Only depend on the following packages, so that there is no cycle.
+                        let dependency = |next: &mut dyn FnMut(u32) -> u32, spread: u32| {
+                            package + 1 + next((packages - package - 1).min(spread))
+                        };
+                        let mut used = Vec::new();
+                        for _ in 0..requirements {
+                            let dependency = dependency(&mut next, 20);
+                            if !used.contains(&dependency) {
+                                used.push(dependency);
+                                dependencies
+                                    .push(Dependency::Requires(dependency, range(&mut next)));
+                            }
+                        }
+                        let mut any_of = Vec::new();
+                        for _ in 0..alternatives {
+                            let dependency = dependency(&mut next, 40);
+                            if !used.contains(&dependency) {
+                                used.push(dependency);
+                                any_of.push((dependency, range(&mut next)));
+                            }
+                        }
+                        if !any_of.is_empty() {
+                            dependencies.push(Dependency::AnyOf(any_of));
+                        }
+                        dependencies
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            versions,
+            dependencies,
+        }
+    }
+}
+
+impl DependencyProvider for ManyAlternatives {
+    type P = u32;
+    type V = u32;
+    type VS = Ranges<u32>;
+    type M = String;
+    type Err = Infallible;
+    type Priority = (Reverse<usize>, Reverse<u32>);
+
+    fn prioritize(
+        &self,
+        package: &u32,
+        range: &Ranges<u32>,
+        _: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        let count = (0..self.versions).filter(|v| range.contains(v)).count();
+        (Reverse(count), Reverse(*package))
+    }
+
+    fn choose_version(&self, _: &u32, range: &Ranges<u32>) -> Result<Option<u32>, Infallible> {
+        Ok((0..self.versions).rev().find(|v| range.contains(v)))
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &u32,
+        version: &u32,
+    ) -> Result<Dependencies<u32, Ranges<u32>, String>, Infallible> {
+        Ok(Dependencies::Extended(
+            self.dependencies[*package as usize][*version as usize].clone(),
+        ))
+    }
+}
+
+fn bench_many_alternatives(c: &mut Criterion) {
+    let mut group = c.benchmark_group("many_alternatives");
+    group.measurement_time(Duration::from_secs(20));
+
+    for (packages, versions, requirements, alternatives) in [(300, 50, 4, 10), (300, 30, 5, 16)] {
+        let dependency_provider =
+            ManyAlternatives::new(packages, versions, requirements, alternatives);
+        let name = format!("{packages}p_{versions}v_{requirements}r_{alternatives}a");
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for version in 0..versions {
+                    let _ = resolve(&dependency_provider, 0, version);
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_many_alternatives);
+criterion_main!(benches);
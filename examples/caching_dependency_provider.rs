@@ -2,7 +2,10 @@
 
 use std::cell::RefCell;
 
-use pubgrub::{resolve, Dependencies, DependencyProvider, OfflineDependencyProvider, Ranges};
+use pubgrub::{
+    resolve, Dependencies, DependencyProvider, OfflineDependencyProvider,
+    PackageResolutionStatistics, Ranges,
+};
 
 type NumVS = Ranges<u32>;
 
@@ -43,6 +46,10 @@ impl<DP: DependencyProvider<M = String>> DependencyProvider for CachingDependenc
                         Ok(Dependencies::Available(dependencies))
                     }
                     Ok(Dependencies::Unavailable(reason)) => Ok(Dependencies::Unavailable(reason)),
+                    // The cache only stores plain requirements.
+                    Ok(Dependencies::Extended(dependencies)) => {
+                        Ok(Dependencies::Extended(dependencies))
+                    }
                     error @ Err(_) => error,
                 }
             }
@@ -57,8 +64,14 @@ impl<DP: DependencyProvider<M = String>> DependencyProvider for CachingDependenc
 
     type Priority = DP::Priority;
 
-    fn prioritize(&self, package: &DP::P, ranges: &DP::VS) -> Self::Priority {
-        self.remote_dependencies.prioritize(package, ranges)
+    fn prioritize(
+        &self,
+        package: &DP::P,
+        ranges: &DP::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.remote_dependencies
+            .prioritize(package, ranges, package_statistics)
     }
 
     type Err = DP::Err;
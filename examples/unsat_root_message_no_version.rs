@@ -100,6 +100,7 @@ impl ReportFormatter<Package, Ranges<SemanticVersion>, String> for CustomReportF
                     format!("{package} {package_set} depends on {dependency} {dependency_set}")
                 }
             }
+            _ => external.to_string(),
         }
     }
 
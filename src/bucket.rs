@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Split packages into compatibility buckets,
+//! each of which can have its own version in a solution.
+//!
+//! PubGrub selects at most one version per package.
+//! Some ecosystems, like Cargo, allow several versions of the same package
+//! as long as they are not compatible with each other, for example `foo 1.x` and `foo 2.x`.
+//! [Bucketed] wraps a [DependencyProvider] speaking in real package names,
+//! and presents each bucket of a package as a distinct [BucketedPackage] to the resolver.
+
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+
+use crate::{
+    Dependencies, Dependency, DependencyProvider, Map, Package, PackageResolutionStatistics,
+    SelectedDependencies, VersionSet,
+};
+
+/// How the versions of packages are split into compatibility buckets.
+pub trait Buckets<P: Package, VS: VersionSet> {
+    /// The identifier of a bucket, for example the major version.
+    type Bucket: Clone + Eq + Hash + Debug + Display;
+
+    /// The bucket a version of a package belongs to.
+    fn bucket(&self, package: &P, version: &VS::V) -> Self::Bucket;
+
+    /// Split a set of versions of a package into the buckets it overlaps,
+    /// with the part of the set that belongs to each of them.
+    ///
+    /// The parts must not overlap, and together they must cover the whole set.
+    fn split(&self, package: &P, versions: &VS) -> Vec<(Self::Bucket, VS)>;
+}
+
+/// A package restricted to one of its buckets.
+///
+/// It is displayed as `package@bucket` in reports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BucketedPackage<P, B> {
+    /// The real package name.
+    pub package: P,
+    /// The bucket of that package.
+    pub bucket: B,
+}
+
+impl<P: Display, B: Display> Display for BucketedPackage<P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.package, self.bucket)
+    }
+}
+
+/// A [DependencyProvider] resolving each bucket of a package as a distinct package.
+///
+/// Requirements on a set of versions spanning several buckets
+/// are satisfied by [any of](Dependency::AnyOf) these buckets,
+/// and constraints apply to all the buckets they overlap.
+pub struct Bucketed<DP, B> {
+    dependency_provider: DP,
+    buckets: B,
+}
+
+impl<DP: DependencyProvider, B: Buckets<DP::P, DP::VS>> Bucketed<DP, B> {
+    /// Wrap a dependency provider, splitting its packages with the given buckets.
+    pub fn new(dependency_provider: DP, buckets: B) -> Self {
+        Self {
+            dependency_provider,
+            buckets,
+        }
+    }
+
+    /// The bucketed package of a package version, for example to start resolving from it.
+    pub fn package(&self, package: DP::P, version: &DP::V) -> BucketedPackage<DP::P, B::Bucket> {
+        let bucket = self.buckets.bucket(&package, version);
+        BucketedPackage { package, bucket }
+    }
+
+    /// Group the selected versions by real package name, one for each bucket.
+    #[allow(clippy::type_complexity)]
+    pub fn versions_by_package(
+        solution: &SelectedDependencies<Self>,
+    ) -> Map<DP::P, Vec<(B::Bucket, DP::V)>> {
+        let mut versions = Map::<_, Vec<_>>::default();
+        for (package, version) in solution {
+            versions
+                .entry(package.package.clone())
+                .or_default()
+                .push((package.bucket.clone(), version.clone()));
+        }
+        for package_versions in versions.values_mut() {
+            package_versions.sort_by(|(_, v1), (_, v2)| v1.cmp(v2));
+        }
+        versions
+    }
+
+    /// The bucketed requirement of a set of versions of a package.
+    fn requires(
+        &self,
+        package: DP::P,
+        versions: DP::VS,
+    ) -> Dependency<BucketedPackage<DP::P, B::Bucket>, DP::VS> {
+        let mut parts = self.bucketed(package, &versions);
+        if parts.len() == 1 {
+            let (package, versions) = parts.pop().unwrap();
+            Dependency::Requires(package, versions)
+        } else {
+            Dependency::AnyOf(parts)
+        }
+    }
+
+    /// The parts of a set of versions of a package in each of its buckets.
+    #[allow(clippy::type_complexity)]
+    fn bucketed(
+        &self,
+        package: DP::P,
+        versions: &DP::VS,
+    ) -> Vec<(BucketedPackage<DP::P, B::Bucket>, DP::VS)> {
+        self.buckets
+            .split(&package, versions)
+            .into_iter()
+            .map(|(bucket, versions)| {
+                let package = BucketedPackage {
+                    package: package.clone(),
+                    bucket,
+                };
+                (package, versions)
+            })
+            .collect()
+    }
+}
+
+impl<DP: DependencyProvider, B: Buckets<DP::P, DP::VS>> DependencyProvider for Bucketed<DP, B> {
+    type P = BucketedPackage<DP::P, B::Bucket>;
+    type V = DP::V;
+    type VS = DP::VS;
+    type M = DP::M;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.dependency_provider
+            .prioritize(&package.package, range, package_statistics)
+    }
+    type Priority = DP::Priority;
+
+    type Err = DP::Err;
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.dependency_provider
+            .choose_version(&package.package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let dependencies = match self
+            .dependency_provider
+            .get_dependencies(&package.package, version)?
+        {
+            Dependencies::Unavailable(reason) => return Ok(Dependencies::Unavailable(reason)),
+            Dependencies::Available(dependencies) => dependencies
+                .into_iter()
+                .map(|(p, versions)| self.requires(p, versions))
+                .collect(),
+            Dependencies::Extended(dependencies) => {
+                let mut bucketed = Vec::with_capacity(dependencies.len());
+                for dependency in dependencies {
+                    match dependency {
+                        Dependency::Requires(p, versions) => {
+                            bucketed.push(self.requires(p, versions))
+                        }
+                        // Every bucket with versions outside the constraint is constrained.
+                        Dependency::Constrains(p, versions) => bucketed.extend(
+                            self.bucketed(p, &versions.complement()).into_iter().map(
+                                |(p, excluded)| Dependency::Constrains(p, excluded.complement()),
+                            ),
+                        ),
+                        Dependency::AnyOf(alternatives) => {
+                            bucketed.push(Dependency::AnyOf(
+                                alternatives
+                                    .into_iter()
+                                    .flat_map(|(p, versions)| self.bucketed(p, &versions))
+                                    .collect(),
+                            ));
+                        }
+                        Dependency::Provides(p, version) => {
+                            bucketed.push(Dependency::Provides(self.package(p, &version), version));
+                        }
+                    }
+                }
+                bucketed
+            }
+        };
+        Ok(Dependencies::Extended(dependencies))
+    }
+
+    fn should_cancel(&self) -> Result<(), Self::Err> {
+        self.dependency_provider.should_cancel()
+    }
+
+    fn prefetch(&self, dependencies: &[(Self::P, Self::VS)]) {
+        let dependencies: Vec<_> = dependencies
+            .iter()
+            .map(|(package, range)| (package.package.clone(), range.clone()))
+            .collect();
+        self.dependency_provider.prefetch(&dependencies);
+    }
+}
@@ -4,24 +4,51 @@
 
 use thiserror::Error;
 
-use crate::{DependencyProvider, DerivationTree};
+use crate::{DerivationTree, Limit, ProviderTypes, ResolutionStats, SelectedDependencies, Set};
 
 /// There is no solution for this set of dependencies.
-pub type NoSolutionError<DP> = DerivationTree<
-    <DP as DependencyProvider>::P,
-    <DP as DependencyProvider>::VS,
-    <DP as DependencyProvider>::M,
->;
+pub type NoSolutionError<DP> =
+    DerivationTree<<DP as ProviderTypes>::P, <DP as ProviderTypes>::VS, <DP as ProviderTypes>::M>;
+
+/// What could be resolved when there is no solution,
+/// from [PubGrubError::NoSolutionWithPartial].
+pub struct PartialResolution<DP: ProviderTypes> {
+    /// Why there is no solution.
+    pub derivation_tree: NoSolutionError<DP>,
+    /// The most versions decided on without conflict during the resolution,
+    /// before backtracking.
+    pub partial_solution: SelectedDependencies<DP>,
+    /// The packages involved in the derivation tree.
+    pub involved_packages: Set<DP::P>,
+}
+
+impl<DP: ProviderTypes> std::fmt::Debug for PartialResolution<DP> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartialResolution")
+            .field("derivation_tree", &self.derivation_tree)
+            .field("partial_solution", &self.partial_solution)
+            .field("involved_packages", &self.involved_packages)
+            .finish()
+    }
+}
 
 /// Errors that may occur while solving dependencies.
 #[derive(Error)]
-pub enum PubGrubError<DP: DependencyProvider> {
+pub enum PubGrubError<DP: ProviderTypes> {
     /// There is no solution for this set of dependencies.
     #[error("No solution")]
     NoSolution(NoSolutionError<DP>),
 
-    /// Error arising when the implementer of [DependencyProvider] returned an error in the method
-    /// [get_dependencies](DependencyProvider::get_dependencies).
+    /// There is no solution for this set of dependencies,
+    /// with what could be resolved before the conflicts.
+    ///
+    /// Returned instead of [NoSolution](PubGrubError::NoSolution) when enabled with
+    /// [partial_solution_on_failure](crate::ResolveOptions::partial_solution_on_failure).
+    #[error("No solution")]
+    NoSolutionWithPartial(Box<PartialResolution<DP>>),
+
+    /// Error arising when the implementer of [DependencyProvider](crate::DependencyProvider)
+    /// returned an error in the method [get_dependencies](crate::DependencyProvider::get_dependencies).
     #[error("Retrieving dependencies of {package} {version} failed")]
     ErrorRetrievingDependencies {
         /// Package whose dependencies we want.
@@ -29,26 +56,48 @@ pub enum PubGrubError<DP: DependencyProvider> {
         /// Version of the package for which we want the dependencies.
         version: DP::V,
         /// Error raised by the implementer of
-        /// [DependencyProvider].
+        /// [DependencyProvider](crate::DependencyProvider).
         source: DP::Err,
     },
 
-    /// Error arising when the implementer of [DependencyProvider] returned an error in the method
-    /// [choose_version](DependencyProvider::choose_version).
+    /// Error arising when the implementer of [DependencyProvider](crate::DependencyProvider)
+    /// returned an error in the method [choose_version](crate::DependencyProvider::choose_version).
     #[error("Decision making failed")]
     ErrorChoosingPackageVersion(#[source] DP::Err),
 
-    /// Error arising when the implementer of [DependencyProvider]
-    /// returned an error in the method [should_cancel](DependencyProvider::should_cancel).
+    /// Error arising when the implementer of [DependencyProvider](crate::DependencyProvider)
+    /// returned an error in the method [should_cancel](crate::DependencyProvider::should_cancel).
     #[error("We should cancel")]
     ErrorInShouldCancel(#[source] DP::Err),
 
+    /// The resolution was stopped because it reached one of the limits
+    /// of its [ResolveOptions](crate::ResolveOptions).
+    #[error("Resolution stopped after reaching {limit}")]
+    LimitReached {
+        /// The limit that was reached.
+        limit: Limit,
+        /// Statistics gathered until the resolution stopped.
+        stats: ResolutionStats,
+    },
+
+    /// The version given to [Solver::add_decision](crate::Solver::add_decision)
+    /// cannot be decided on: the partial solution does not allow it,
+    /// the package already has a decision,
+    /// or the dependencies of that version were not added first.
+    #[error("Cannot decide on {package} {version}")]
+    InvalidDecision {
+        /// The package to decide on.
+        package: DP::P,
+        /// The version that cannot be decided on.
+        version: DP::V,
+    },
+
     /// Something unexpected happened.
     #[error("{0}")]
     Failure(String),
 }
 
-impl<DP: DependencyProvider> From<NoSolutionError<DP>> for PubGrubError<DP> {
+impl<DP: ProviderTypes> From<NoSolutionError<DP>> for PubGrubError<DP> {
     fn from(err: NoSolutionError<DP>) -> Self {
         Self::NoSolution(err)
     }
@@ -56,11 +105,15 @@ impl<DP: DependencyProvider> From<NoSolutionError<DP>> for PubGrubError<DP> {
 
 impl<DP> std::fmt::Debug for PubGrubError<DP>
 where
-    DP: DependencyProvider,
+    DP: ProviderTypes,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::NoSolution(err) => f.debug_tuple("NoSolution").field(&err).finish(),
+            Self::NoSolutionWithPartial(partial) => f
+                .debug_tuple("NoSolutionWithPartial")
+                .field(partial)
+                .finish(),
             Self::ErrorRetrievingDependencies {
                 package,
                 version,
@@ -78,6 +131,16 @@ where
             Self::ErrorInShouldCancel(arg0) => {
                 f.debug_tuple("ErrorInShouldCancel").field(arg0).finish()
             }
+            Self::LimitReached { limit, stats } => f
+                .debug_struct("LimitReached")
+                .field("limit", limit)
+                .field("stats", stats)
+                .finish(),
+            Self::InvalidDecision { package, version } => f
+                .debug_struct("InvalidDecision")
+                .field("package", package)
+                .field("version", version)
+                .finish(),
             Self::Failure(arg0) => f.debug_tuple("Failure").field(arg0).finish(),
         }
     }
@@ -1,8 +1,12 @@
 use std::fmt;
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasherDefault, Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Index, Range};
 
+use rustc_hash::FxHasher;
+
+type FnvIndexSet<V> = indexmap::IndexSet<V, BuildHasherDefault<FxHasher>>;
+
 /// The index of a value allocated in an arena that holds `T`s.
 ///
 /// The Clone, Copy and other traits are defined manually because
@@ -109,6 +113,13 @@ impl<T> Arena<T> {
         let end = Id::from(self.data.len() as u32);
         Range { start, end }
     }
+
+    /// Iterate over the values, in allocation order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> {
+        (0..)
+            .zip(self.data.iter())
+            .map(|(raw, value)| (Id::from(raw), value))
+    }
 }
 
 impl<T> Index<Id<T>> for Arena<T> {
@@ -124,3 +135,56 @@ impl<T> Index<Range<Id<T>>> for Arena<T> {
         &self.data[(id.start.raw as usize)..(id.end.raw as usize)]
     }
 }
+
+/// Yet another index-based arena, where each value is only allocated once.
+///
+/// Allocating a value that is already in the arena returns the id it already has,
+/// so that values can be compared and hashed through their id instead.
+/// The solver uses it to intern packages, and works with their ids
+/// instead of cloning and hashing the packages themselves.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct HashArena<T: Hash + Eq> {
+    data: FnvIndexSet<T>,
+}
+
+impl<T: Hash + Eq + fmt::Debug> fmt::Debug for HashArena<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("HashArena")
+            .field("len", &self.data.len())
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<T: Hash + Eq> Default for HashArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq> HashArena<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: FnvIndexSet::default(),
+        }
+    }
+
+    pub(crate) fn alloc(&mut self, value: T) -> Id<T> {
+        let (raw, _) = self.data.insert_full(value);
+        Id::from(raw as u32)
+    }
+
+    /// The id of a value, if it was already allocated.
+    pub(crate) fn get_id(&self, value: &T) -> Option<Id<T>> {
+        self.data
+            .get_index_of(value)
+            .map(|raw| Id::from(raw as u32))
+    }
+}
+
+impl<T: Hash + Eq> Index<Id<T>> for HashArena<T> {
+    type Output = T;
+    fn index(&self, id: Id<T>) -> &T {
+        &self.data[id.raw as usize]
+    }
+}
@@ -7,19 +7,42 @@ use std::collections::HashSet as Set;
 use std::sync::Arc;
 
 use crate::internal::{
-    Arena, DecisionLevel, IncompDpId, Incompatibility, PartialSolution, Relation, SatisfierSearch,
-    SmallVec,
+    Arena, Assignment, DecisionLevel, HashArena, Id, IncompDpId, Incompatibility, PartialSolution,
+    Relation, SatisfierSearch, SmallVec,
+};
+use crate::{
+    term, Dependency, DerivationTree, LearnedIncompatibilities, Map, NoSolutionError, Observer,
+    PackageResolutionStatistics, ProviderTypes, ResolutionStats, SelectedDependencies,
+    SelectionStep, Term, VersionSet,
 };
-use crate::{DependencyProvider, DerivationTree, Map, NoSolutionError, VersionSet};
 
 /// Current state of the PubGrub algorithm.
-#[derive(Clone)]
-pub(crate) struct State<DP: DependencyProvider> {
-    root_package: DP::P,
-    root_version: DP::V,
+pub(crate) struct State<DP: ProviderTypes> {
+    /// The package and version whose dependencies we are solving,
+    /// or [None] when solving a set of requirements.
+    root: Option<(Id<DP::P>, DP::V)>,
+
+    #[allow(clippy::type_complexity)]
+    incompatibilities: Map<Id<DP::P>, Vec<IncompDpId<DP>>>,
 
+    /// For each package, the incompatibilities with more than two terms watching its term.
+    /// Unit propagation only evaluates those watching a changed package,
+    /// see [State::watched].
+    ///
+    /// Incompatibilities with at most two terms, like dependencies, do not watch any term:
+    /// they are evaluated every time one of their packages changes.
+    #[allow(clippy::type_complexity)]
+    watchers: Map<Id<DP::P>, Vec<IncompDpId<DP>>>,
+
+    /// The packages of the two terms watched by each incompatibility with more than two terms,
+    /// indexed by incompatibility id.
+    ///
+    /// Whenever possible, the watched terms are not satisfied by the partial solution,
+    /// so that the incompatibility cannot become almost satisfied without one of them changing.
+    /// Otherwise the watched terms are the ones satisfied at the highest decision levels,
+    /// so that they stop being satisfied first when backtracking.
     #[allow(clippy::type_complexity)]
-    incompatibilities: Map<DP::P, Vec<IncompDpId<DP>>>,
+    watched: Vec<Option<(Id<DP::P>, Id<DP::P>)>>,
 
     /// Store the ids of incompatibilities that are already contradicted.
     /// For each one keep track of the decision level when it was found to be contradicted.
@@ -29,7 +52,15 @@ pub(crate) struct State<DP: DependencyProvider> {
     /// All incompatibilities expressing dependencies,
     /// with common dependents merged.
     #[allow(clippy::type_complexity)]
-    merged_dependencies: Map<(DP::P, DP::P), SmallVec<IncompDpId<DP>>>,
+    merged_dependencies: Map<(Id<DP::P>, Id<DP::P>), SmallVec<IncompDpId<DP>>>,
+
+    /// All incompatibilities expressing dependencies on any of several packages.
+    any_dependencies: Vec<IncompDpId<DP>>,
+
+    /// For each virtual package, the incompatibilities expressing
+    /// that a package version provides one of its versions.
+    #[allow(clippy::type_complexity)]
+    provides: Map<Id<DP::P>, Vec<IncompDpId<DP>>>,
 
     /// Partial solution.
     /// TODO: remove pub.
@@ -38,31 +69,216 @@ pub(crate) struct State<DP: DependencyProvider> {
     /// The store is the reference storage for all incompatibilities.
     pub(crate) incompatibility_store: Arena<Incompatibility<DP::P, DP::VS, DP::M>>,
 
+    /// The store of all the packages the resolution knows about.
+    /// Everything else refers to packages by their id in the store,
+    /// which is cheaper to copy, hash and compare than a package.
+    pub(crate) package_store: HashArena<DP::P>,
+
     /// This is a stack of work to be done in `unit_propagation`.
     /// It can definitely be a local variable to that method, but
     /// this way we can reuse the same allocation for better performance.
-    unit_propagation_buffer: SmallVec<DP::P>,
+    unit_propagation_buffer: SmallVec<Id<DP::P>>,
+
+    /// Counters of what happened during the resolution so far.
+    pub(crate) stats: ResolutionStats,
+
+    /// How often each package was involved in conflicts so far, given to the prioritizer.
+    pub(crate) package_statistics: Map<Id<DP::P>, PackageResolutionStatistics>,
+
+    /// The most decisions that were consistent when a conflict happened, if they are tracked.
+    best_decisions: Option<SelectedDependencies<DP>>,
 }
 
-impl<DP: DependencyProvider> State<DP> {
+// Implemented by hand so that the dependency provider does not need to be cloneable.
+impl<DP: ProviderTypes> Clone for State<DP> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            incompatibilities: self.incompatibilities.clone(),
+            watchers: self.watchers.clone(),
+            watched: self.watched.clone(),
+            contradicted_incompatibilities: self.contradicted_incompatibilities.clone(),
+            merged_dependencies: self.merged_dependencies.clone(),
+            any_dependencies: self.any_dependencies.clone(),
+            provides: self.provides.clone(),
+            partial_solution: self.partial_solution.clone(),
+            incompatibility_store: self.incompatibility_store.clone(),
+            package_store: self.package_store.clone(),
+            unit_propagation_buffer: self.unit_propagation_buffer.clone(),
+            stats: self.stats.clone(),
+            package_statistics: self.package_statistics.clone(),
+            best_decisions: self.best_decisions.clone(),
+        }
+    }
+}
+
+impl<DP: ProviderTypes> State<DP> {
     /// Initialization of PubGrub state.
     pub(crate) fn init(root_package: DP::P, root_version: DP::V) -> Self {
+        let mut package_store = HashArena::new();
+        let root_package = package_store.alloc(root_package);
         let mut incompatibility_store = Arena::new();
         let not_root_id = incompatibility_store.alloc(Incompatibility::not_root(
-            root_package.clone(),
+            root_package,
             root_version.clone(),
         ));
-        let mut incompatibilities = Map::default();
-        incompatibilities.insert(root_package.clone(), vec![not_root_id]);
-        Self {
-            root_package,
-            root_version,
-            incompatibilities,
+        let mut state = Self {
+            root: Some((root_package, root_version)),
+            incompatibilities: Map::default(),
+            watchers: Map::default(),
+            watched: Vec::new(),
             contradicted_incompatibilities: Map::default(),
             partial_solution: PartialSolution::empty(),
             incompatibility_store,
+            package_store,
+            unit_propagation_buffer: SmallVec::Empty,
+            merged_dependencies: Map::default(),
+            any_dependencies: Vec::new(),
+            provides: Map::default(),
+            stats: ResolutionStats::default(),
+            package_statistics: Map::default(),
+            best_decisions: None,
+        };
+        state.merge_incompatibility(not_root_id);
+        state
+    }
+
+    /// Initialization of PubGrub state from a set of requirements
+    /// and excluded versions, instead of a root package.
+    pub(crate) fn init_requirements(
+        requirements: impl IntoIterator<Item = (DP::P, DP::VS)>,
+        exclusions: impl IntoIterator<Item = (DP::P, DP::VS)>,
+    ) -> Self {
+        let mut state = Self {
+            root: None,
+            incompatibilities: Map::default(),
+            watchers: Map::default(),
+            watched: Vec::new(),
+            contradicted_incompatibilities: Map::default(),
+            partial_solution: PartialSolution::empty(),
+            incompatibility_store: Arena::new(),
+            package_store: HashArena::new(),
             unit_propagation_buffer: SmallVec::Empty,
             merged_dependencies: Map::default(),
+            any_dependencies: Vec::new(),
+            provides: Map::default(),
+            stats: ResolutionStats::default(),
+            package_statistics: Map::default(),
+            best_decisions: None,
+        };
+        for (package, set) in requirements {
+            let package = state.package_store.alloc(package);
+            let id = state
+                .incompatibility_store
+                .alloc(Incompatibility::requirement(package, set));
+            if state.incompatibility_store[id].iter().next().is_none() {
+                // An impossible requirement has no term left,
+                // so we index it by its package for unit propagation to find it.
+                state.incompatibilities.entry(package).or_default().push(id);
+            } else {
+                state.merge_incompatibility(id);
+            }
+        }
+        for (package, set) in exclusions {
+            let package = state.package_store.alloc(package);
+            // Excluding no version at all is not a constraint.
+            if set != DP::VS::empty() {
+                state.add_incompatibility(Incompatibility::exclusion(package, set));
+            }
+        }
+        state
+    }
+
+    /// Keep a package at its locked version if it is selected,
+    /// unless it is the root package, whose version is already known.
+    pub(crate) fn lock(&mut self, package: DP::P, version: DP::V) {
+        let package = self.package_store.alloc(package);
+        if self.root.as_ref().is_some_and(|(root, _)| root == &package) {
+            return;
+        }
+        self.add_incompatibility(Incompatibility::locked(package, version));
+    }
+
+    /// The learned incompatibilities only derived from facts of the dependency provider,
+    /// with all the incompatibilities they are derived from.
+    pub(crate) fn learned_incompatibilities(
+        &self,
+    ) -> LearnedIncompatibilities<DP::P, DP::VS, DP::M> {
+        // Causes are allocated before the incompatibilities derived from them,
+        // so a single pass in allocation order is enough.
+        let mut from_provider = Vec::new();
+        for (_, incompat) in self.incompatibility_store.iter() {
+            from_provider.push(match incompat.causes() {
+                Some((id1, id2)) => from_provider[id1.into_raw()] && from_provider[id2.into_raw()],
+                None => incompat.is_provider_fact(),
+            });
+        }
+        let mut learned: Vec<_> = self
+            .incompatibilities
+            .values()
+            .flatten()
+            .copied()
+            .filter(|id| {
+                self.incompatibility_store[*id].causes().is_some() && from_provider[id.into_raw()]
+            })
+            .collect::<Set<_>>()
+            .into_iter()
+            .collect();
+        learned.sort_by_key(|id| id.into_raw());
+
+        let mut needed = vec![false; from_provider.len()];
+        let mut stack = learned.clone();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut needed[id.into_raw()], true) {
+                stack.extend(
+                    self.incompatibility_store[id]
+                        .causes()
+                        .map(|(a, b)| [a, b])
+                        .into_iter()
+                        .flatten(),
+                );
+            }
+        }
+
+        // Copy them into their own stores, so that they do not keep the whole resolution alive.
+        let mut exported = LearnedIncompatibilities {
+            incompatibility_store: Arena::new(),
+            package_store: HashArena::new(),
+            learned: Vec::with_capacity(learned.len()),
+        };
+        let mut ids = vec![None; needed.len()];
+        for (id, incompat) in self.incompatibility_store.iter() {
+            if !needed[id.into_raw()] {
+                continue;
+            }
+            let translated = incompat.translate(
+                |p| exported.package_store.alloc(self.package_store[p].clone()),
+                |cause| ids[cause.into_raw()].expect("causes are allocated first"),
+            );
+            ids[id.into_raw()] = Some(exported.incompatibility_store.alloc(translated));
+        }
+        exported.learned = learned
+            .into_iter()
+            .map(|id| ids[id.into_raw()].unwrap())
+            .collect();
+        exported
+    }
+
+    /// Add incompatibilities learned by another resolution, with the same dependency provider.
+    pub(crate) fn add_learned_incompatibilities(
+        &mut self,
+        learned: &LearnedIncompatibilities<DP::P, DP::VS, DP::M>,
+    ) {
+        let mut ids = Vec::new();
+        for (_, incompat) in learned.incompatibility_store.iter() {
+            let translated = incompat.translate(
+                |p| self.package_store.alloc(learned.package_store[p].clone()),
+                |cause| ids[cause.into_raw()],
+            );
+            ids.push(self.incompatibility_store.alloc(translated));
+        }
+        for id in &learned.learned {
+            self.merge_incompatibility(ids[id.into_raw()]);
         }
     }
 
@@ -72,98 +288,298 @@ impl<DP: DependencyProvider> State<DP> {
         self.merge_incompatibility(id);
     }
 
+    /// Add an incompatibility satisfied by the partial solution,
+    /// and return the package unit propagation must start from to find the conflict.
+    ///
+    /// Its terms may be satisfied by derivations made before the decisions,
+    /// so this is not necessarily the package of the last decision.
+    pub(crate) fn add_satisfied_incompatibility(
+        &mut self,
+        incompat: Incompatibility<DP::P, DP::VS, DP::M>,
+    ) -> Option<Id<DP::P>> {
+        let id = self.incompatibility_store.alloc(incompat);
+        self.merge_incompatibility(id);
+        match self.watched.get(id.into_raw()) {
+            Some(Some((package, _))) => Some(*package),
+            _ => self.incompatibility_store[id]
+                .iter()
+                .next()
+                .map(|(package, _)| package),
+        }
+    }
+
     /// Add an incompatibility to the state.
     pub(crate) fn add_incompatibility_from_dependencies(
         &mut self,
-        package: DP::P,
+        package: Id<DP::P>,
         version: DP::V,
-        deps: impl IntoIterator<Item = (DP::P, DP::VS)>,
+        deps: impl IntoIterator<Item = Dependency<DP::P, DP::VS>>,
     ) -> std::ops::Range<IncompDpId<DP>> {
+        let package_store = &mut self.package_store;
         // Create incompatibilities and allocate them in the store.
         let new_incompats_id_range =
             self.incompatibility_store
                 .alloc_iter(deps.into_iter().map(|dep| {
-                    Incompatibility::from_dependency(
-                        package.clone(),
-                        <DP::VS as VersionSet>::singleton(version.clone()),
-                        dep,
-                    )
+                    let versions = <DP::VS as VersionSet>::singleton(version.clone());
+                    let mut intern = |(p, set)| (package_store.alloc(p), set);
+                    match dep {
+                        Dependency::Requires(p, set) => {
+                            Incompatibility::from_dependency(package, versions, intern((p, set)))
+                        }
+                        Dependency::Constrains(p, set) => {
+                            Incompatibility::from_constraint(package, versions, intern((p, set)))
+                        }
+                        Dependency::AnyOf(alternatives) => Incompatibility::from_any_dependency(
+                            package,
+                            versions,
+                            alternatives.into_iter().map(intern).collect(),
+                        ),
+                        Dependency::Provides(p, version) => Incompatibility::provides(
+                            package,
+                            versions,
+                            (package_store.alloc(p), version),
+                        ),
+                    }
                 }));
         // Merge the newly created incompatibilities with the older ones.
         for id in IncompDpId::<DP>::range_to_iter(new_incompats_id_range.clone()) {
+            let incompat = &self.incompatibility_store[id];
+            if incompat.as_any_dependency().is_some() {
+                self.any_dependencies.push(id);
+            }
+            if let Some((_, _, virtual_package, _)) = incompat.as_provides() {
+                self.provides.entry(virtual_package).or_default().push(id);
+            }
             self.merge_incompatibility(id);
         }
         new_incompats_id_range
     }
 
+    /// Find a dependency on any of several packages whose dependent is selected
+    /// but none of the alternatives is, and return the first alternative that is still possible,
+    /// with the versions it can be selected in.
+    ///
+    /// Unit propagation only derives an alternative once all the others are excluded,
+    /// so one of them has to be decided on when there is nothing else left to pick.
+    pub(crate) fn unselected_alternative(&self) -> Option<(Id<DP::P>, DP::VS)> {
+        'dependencies: for &id in &self.any_dependencies {
+            let incompat = &self.incompatibility_store[id];
+            let Some((dependent, _, alternatives)) = incompat.as_any_dependency() else {
+                continue;
+            };
+            let relation = |package: Id<DP::P>, term: &Term<DP::VS>| {
+                self.partial_solution
+                    .term_intersection_for_package(package)
+                    .map(|intersection| term.relation_with(intersection))
+            };
+            if !matches!(
+                incompat
+                    .get(dependent)
+                    .filter(|term| term.is_positive())
+                    .and_then(|term| relation(dependent, term)),
+                Some(term::Relation::Satisfied)
+            ) {
+                continue;
+            }
+            let mut alternative = None;
+            for &(package, _) in alternatives {
+                let Some(term) = incompat.get(package).filter(|term| !term.is_positive()) else {
+                    continue;
+                };
+                match relation(package, term) {
+                    // That alternative is excluded.
+                    Some(term::Relation::Satisfied) => {}
+                    // That alternative is selected.
+                    Some(term::Relation::Contradicted) => continue 'dependencies,
+                    Some(term::Relation::Inconclusive) => {
+                        if alternative.is_none() {
+                            let intersection = self
+                                .partial_solution
+                                .term_intersection_for_package(package)
+                                .unwrap();
+                            alternative = Some((
+                                package,
+                                intersection
+                                    .intersection(&term.negate())
+                                    .unwrap_positive()
+                                    .clone(),
+                            ));
+                        }
+                    }
+                    None => {
+                        if alternative.is_none() {
+                            alternative = Some((package, term.negate().unwrap_positive().clone()));
+                        }
+                    }
+                }
+            }
+            if alternative.is_some() {
+                return alternative;
+            }
+        }
+        None
+    }
+
+    /// The decided package providing a virtual package, with the versions of it that do,
+    /// and the version of the virtual package they provide.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn provider_of(
+        &self,
+        virtual_package: Id<DP::P>,
+    ) -> Option<(Id<DP::P>, &DP::VS, &DP::V)> {
+        self.provides.get(&virtual_package)?.iter().find_map(|&id| {
+            let (provider, versions, _, version) = self.incompatibility_store[id].as_provides()?;
+            self.partial_solution
+                .decision(provider)
+                .is_some_and(|provider_version| versions.contains(provider_version))
+                .then_some((provider, versions, version))
+        })
+    }
+
+    /// The decided package providing a virtual package decided on the version it provides,
+    /// with the versions of it that do.
+    pub(crate) fn decided_provider(
+        &self,
+        virtual_package: Id<DP::P>,
+    ) -> Option<(Id<DP::P>, &DP::VS)> {
+        let (provider, versions, version) = self.provider_of(virtual_package)?;
+        (self.partial_solution.decision(virtual_package) == Some(version))
+            .then_some((provider, versions))
+    }
+
+    /// The dependencies of the decided package versions on other decided packages,
+    /// with the versions each of them requires, in the order of the decisions.
+    ///
+    /// A dependency on any of several packages leads to its first decided alternative,
+    /// and a dependency on a virtual package leads to the decided package providing it,
+    /// with the versions of it that do. Virtual packages have no edges of their own.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn dependency_edges(&self) -> Vec<(Id<DP::P>, Id<DP::P>, &DP::VS)> {
+        let mut edges = Vec::new();
+        for (package, version) in self.partial_solution.decisions() {
+            // The provider of a virtual package stands in for it.
+            if self.decided_provider(package).is_some() {
+                continue;
+            }
+            let Some(ids) = self.incompatibilities.get(&package) else {
+                continue;
+            };
+            for &id in ids {
+                let incompat = &self.incompatibility_store[id];
+                let edge = if let Some((p1, set1, p2, set2)) = incompat.as_dependency_ranges() {
+                    (p1 == package && set1.contains(version))
+                        .then_some((p2, set2))
+                        .filter(|(p2, _)| self.partial_solution.decision(*p2).is_some())
+                } else if let Some((p1, set1, alternatives)) = incompat.as_any_dependency() {
+                    (p1 == package && set1.contains(version))
+                        .then(|| {
+                            alternatives.iter().find(|(p2, set2)| {
+                                self.partial_solution
+                                    .decision(*p2)
+                                    .is_some_and(|v2| set2.contains(v2))
+                            })
+                        })
+                        .flatten()
+                        .map(|(p2, set2)| (*p2, set2))
+                } else {
+                    None
+                };
+                if let Some((dependency, range)) = edge {
+                    let (dependency, range) = self
+                        .decided_provider(dependency)
+                        .unwrap_or((dependency, range));
+                    edges.push((package, dependency, range));
+                }
+            }
+        }
+        edges
+    }
+
     /// Unit propagation is the core mechanism of the solving algorithm.
     /// CF <https://github.com/dart-lang/pub/blob/master/doc/solver.md#unit-propagation>
-    pub(crate) fn unit_propagation(&mut self, package: DP::P) -> Result<(), NoSolutionError<DP>> {
+    pub(crate) fn unit_propagation(
+        &mut self,
+        package: Id<DP::P>,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<(), NoSolutionError<DP>> {
         self.unit_propagation_buffer.clear();
         self.unit_propagation_buffer.push(package);
         while let Some(current_package) = self.unit_propagation_buffer.pop() {
+            let mut conflict_id = None;
+            // Incompatibilities with at most two terms are cheaper to evaluate
+            // than to keep watching, so all of the current package ones are evaluated.
             // Iterate over incompatibilities in reverse order
             // to evaluate first the newest incompatibilities.
-            let mut conflict_id = None;
-            // We only care about incompatibilities if it contains the current package.
-            for &incompat_id in self.incompatibilities[&current_package].iter().rev() {
-                if self
-                    .contradicted_incompatibilities
-                    .contains_key(&incompat_id)
+            let incompatibilities = self
+                .incompatibilities
+                .get_mut(&current_package)
+                .map(std::mem::take);
+            for &incompat_id in incompatibilities.iter().flatten().rev() {
+                if self.incompatibility_store[incompat_id].len() > 2
+                    || self
+                        .contradicted_incompatibilities
+                        .contains_key(&incompat_id)
                 {
                     continue;
                 }
-                let current_incompat = &self.incompatibility_store[incompat_id];
-                match self.partial_solution.relation(current_incompat) {
-                    // If the partial solution satisfies the incompatibility
-                    // we must perform conflict resolution.
-                    Relation::Satisfied => {
-                        log::info!(
-                            "Start conflict resolution because incompat satisfied:\n   {}",
-                            current_incompat
-                        );
+                match self.relation_update(incompat_id) {
+                    WatchUpdate::Conflict => {
                         conflict_id = Some(incompat_id);
                         break;
                     }
-                    Relation::AlmostSatisfied(package_almost) => {
-                        // Add `package_almost` to the `unit_propagation_buffer` set.
-                        // Putting items in `unit_propagation_buffer` more than once waste cycles,
-                        // but so does allocating a hash map and hashing each item.
-                        // In practice `unit_propagation_buffer` is small enough that we can just do a linear scan.
-                        if !self.unit_propagation_buffer.contains(&package_almost) {
-                            self.unit_propagation_buffer.push(package_almost.clone());
-                        }
-                        // Add (not term) to the partial solution with incompat as cause.
-                        self.partial_solution.add_derivation(
-                            package_almost,
-                            incompat_id,
-                            &self.incompatibility_store,
-                        );
-                        // With the partial solution updated, the incompatibility is now contradicted.
-                        self.contradicted_incompatibilities
-                            .insert(incompat_id, self.partial_solution.current_decision_level());
+                    WatchUpdate::AlmostSatisfied(package_almost) => {
+                        self.derive(package_almost, incompat_id, observer);
                     }
-                    Relation::Contradicted(_) => {
-                        self.contradicted_incompatibilities
-                            .insert(incompat_id, self.partial_solution.current_decision_level());
+                    WatchUpdate::Kept | WatchUpdate::Moved => {}
+                }
+            }
+            if let Some(incompatibilities) = incompatibilities {
+                self.incompatibilities
+                    .insert(current_package, incompatibilities);
+            }
+            // Of the larger incompatibilities, we only care about the ones watching
+            // the current package, the relation of the others to the partial solution
+            // cannot have changed in a way that requires propagation.
+            if conflict_id.is_none() {
+                if let Some(watchers) = self.watchers.get_mut(&current_package) {
+                    let mut watching = std::mem::take(watchers);
+                    let mut index = watching.len();
+                    while index > 0 {
+                        index -= 1;
+                        let incompat_id = watching[index];
+                        match self.watch_update(incompat_id, current_package) {
+                            WatchUpdate::Kept => {}
+                            // The last incompatibility was already evaluated, so it can take its place.
+                            WatchUpdate::Moved => {
+                                watching.swap_remove(index);
+                            }
+                            WatchUpdate::Conflict => {
+                                conflict_id = Some(incompat_id);
+                                break;
+                            }
+                            WatchUpdate::AlmostSatisfied(package_almost) => {
+                                self.derive(package_almost, incompat_id, observer);
+                            }
+                        }
                     }
-                    _ => {}
+                    self.watchers.insert(current_package, watching);
                 }
             }
             if let Some(incompat_id) = conflict_id {
                 let (package_almost, root_cause) =
-                    self.conflict_resolution(incompat_id)
-                        .map_err(|terminal_incompat_id| {
-                            self.build_derivation_tree(terminal_incompat_id)
-                        })?;
+                    self.conflict_resolution(incompat_id, observer).map_err(
+                        |terminal_incompat_id| self.build_derivation_tree(terminal_incompat_id),
+                    )?;
                 self.unit_propagation_buffer.clear();
-                self.unit_propagation_buffer.push(package_almost.clone());
+                self.unit_propagation_buffer.push(package_almost);
                 // Add to the partial solution with incompat as cause.
-                self.partial_solution.add_derivation(
+                let term = self.partial_solution.add_derivation(
                     package_almost,
                     root_cause,
                     &self.incompatibility_store,
                 );
+                observer.derivation(&self.package_store[package_almost], term);
+                self.stats.unit_propagations += 1;
                 // After conflict resolution and the partial solution update,
                 // the root cause incompatibility is now contradicted.
                 self.contradicted_incompatibilities
@@ -174,34 +590,175 @@ impl<DP: DependencyProvider> State<DP> {
         Ok(())
     }
 
+    /// Derive the negation of the term of an almost satisfied incompatibility,
+    /// and schedule its package for unit propagation.
+    fn derive(
+        &mut self,
+        package_almost: Id<DP::P>,
+        incompat_id: IncompDpId<DP>,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) {
+        // Add `package_almost` to the `unit_propagation_buffer` set.
+        // Putting items in `unit_propagation_buffer` more than once waste cycles,
+        // but so does allocating a hash map and hashing each item.
+        // In practice `unit_propagation_buffer` is small enough that we can just do a linear scan.
+        if !self.unit_propagation_buffer.contains(&package_almost) {
+            self.unit_propagation_buffer.push(package_almost);
+        }
+        // Add (not term) to the partial solution with incompat as cause.
+        let term = self.partial_solution.add_derivation(
+            package_almost,
+            incompat_id,
+            &self.incompatibility_store,
+        );
+        observer.derivation(&self.package_store[package_almost], term);
+        self.stats.unit_propagations += 1;
+        // With the partial solution updated, the incompatibility is now contradicted.
+        self.contradicted_incompatibilities
+            .insert(incompat_id, self.partial_solution.current_decision_level());
+    }
+
+    /// Evaluate an incompatibility watching a package whose assignments changed.
+    ///
+    /// If the term of that package is now satisfied, the incompatibility watches
+    /// another term that is not satisfied instead, if there is one.
+    /// Otherwise its relation to the partial solution depends on the other watched term.
+    fn watch_update(
+        &mut self,
+        incompat_id: IncompDpId<DP>,
+        package: Id<DP::P>,
+    ) -> WatchUpdate<DP::P> {
+        if self
+            .contradicted_incompatibilities
+            .contains_key(&incompat_id)
+        {
+            return WatchUpdate::Kept;
+        }
+        let incompat = &self.incompatibility_store[incompat_id];
+        let relation = |package: Id<DP::P>, term: &Term<DP::VS>| {
+            self.partial_solution
+                .term_intersection_for_package(package)
+                .map_or(term::Relation::Inconclusive, |intersection| {
+                    term.relation_with(intersection)
+                })
+        };
+        let Some((first, second)) = self.watched[incompat_id.into_raw()] else {
+            unreachable!("incompatibilities with more than two terms are watched")
+        };
+        let other = if first == package { second } else { first };
+        let watched_relation =
+            |package: Id<DP::P>| incompat.get(package).map(|term| relation(package, term));
+        match watched_relation(package) {
+            Some(term::Relation::Satisfied) => {
+                let replacement = incompat.iter().find(|(p, term)| {
+                    *p != package
+                        && *p != other
+                        && !matches!(relation(*p, term), term::Relation::Satisfied)
+                });
+                if let Some((replacement, term)) = replacement {
+                    let contradicted =
+                        matches!(relation(replacement, term), term::Relation::Contradicted);
+                    self.watched[incompat_id.into_raw()] = Some((other, replacement));
+                    self.watchers
+                        .entry(replacement)
+                        .or_default()
+                        .push(incompat_id);
+                    if contradicted {
+                        self.contradicted_incompatibilities
+                            .insert(incompat_id, self.partial_solution.current_decision_level());
+                    }
+                    return WatchUpdate::Moved;
+                }
+            }
+            Some(term::Relation::Contradicted) => {
+                self.contradicted_incompatibilities
+                    .insert(incompat_id, self.partial_solution.current_decision_level());
+                return WatchUpdate::Kept;
+            }
+            // The other watched term may have been satisfied before
+            // the last backtracking or before the incompatibility was added,
+            // so the incompatibility may be almost satisfied.
+            _ => match watched_relation(other) {
+                Some(term::Relation::Satisfied) => {}
+                Some(term::Relation::Contradicted) => {
+                    self.contradicted_incompatibilities
+                        .insert(incompat_id, self.partial_solution.current_decision_level());
+                    return WatchUpdate::Kept;
+                }
+                _ => return WatchUpdate::Kept,
+            },
+        }
+        // All the terms but the ones of the other watched package are satisfied.
+        self.relation_update(incompat_id)
+    }
+
+    /// Evaluate an incompatibility against the partial solution.
+    fn relation_update(&mut self, incompat_id: IncompDpId<DP>) -> WatchUpdate<DP::P> {
+        let incompat = &self.incompatibility_store[incompat_id];
+        match self.partial_solution.relation(incompat) {
+            // If the partial solution satisfies the incompatibility
+            // we must perform conflict resolution.
+            Relation::Satisfied => {
+                log::info!(
+                    "Start conflict resolution because incompat satisfied:\n   {}",
+                    incompat.display(&self.package_store)
+                );
+                WatchUpdate::Conflict
+            }
+            Relation::AlmostSatisfied(package_almost) => {
+                WatchUpdate::AlmostSatisfied(package_almost)
+            }
+            Relation::Contradicted(_) => {
+                self.contradicted_incompatibilities
+                    .insert(incompat_id, self.partial_solution.current_decision_level());
+                WatchUpdate::Kept
+            }
+            Relation::Inconclusive => WatchUpdate::Kept,
+        }
+    }
+
     /// Return the root cause or the terminal incompatibility.
     /// CF <https://github.com/dart-lang/pub/blob/master/doc/solver.md#unit-propagation>
     #[allow(clippy::type_complexity)]
     fn conflict_resolution(
         &mut self,
         incompatibility: IncompDpId<DP>,
-    ) -> Result<(DP::P, IncompDpId<DP>), IncompDpId<DP>> {
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<(Id<DP::P>, IncompDpId<DP>), IncompDpId<DP>> {
+        self.stats.conflicts += 1;
+        observer
+            .conflict(self.incompatibility_store[incompatibility].as_terms(&self.package_store));
+        self.update_best_decisions();
         let mut current_incompat_id = incompatibility;
         let mut current_incompat_changed = false;
         loop {
-            if self.incompatibility_store[current_incompat_id]
-                .is_terminal(&self.root_package, &self.root_version)
-            {
+            if self.incompatibility_store[current_incompat_id].is_terminal(self.root.as_ref()) {
                 return Err(current_incompat_id);
             } else {
                 let (package, satisfier_search_result) = self.partial_solution.satisfier_search(
                     &self.incompatibility_store[current_incompat_id],
                     &self.incompatibility_store,
+                    self.lowest_backtrack_level(),
                 );
+                // The priorities are all computed again after backtracking,
+                // so the updated statistics are taken into account.
+                for (p, _) in self.incompatibility_store[current_incompat_id].iter() {
+                    let statistics = self.package_statistics.entry(p).or_default();
+                    if p == package {
+                        statistics.unit_propagation_affected += 1;
+                    } else {
+                        statistics.unit_propagation_culprit += 1;
+                    }
+                }
                 match satisfier_search_result {
                     SatisfierSearch::DifferentDecisionLevels {
                         previous_satisfier_level,
                     } => {
-                        let package = package.clone();
                         self.backtrack(
                             current_incompat_id,
                             current_incompat_changed,
                             previous_satisfier_level,
+                            observer,
                         );
                         log::info!("backtrack to {:?}", previous_satisfier_level);
                         return Ok((package, current_incompat_id));
@@ -213,7 +770,7 @@ impl<DP: DependencyProvider> State<DP> {
                             package,
                             &self.incompatibility_store,
                         );
-                        log::info!("prior cause: {}", prior_cause);
+                        log::info!("prior cause: {}", prior_cause.display(&self.package_store));
                         current_incompat_id = self.incompatibility_store.alloc(prior_cause);
                         current_incompat_changed = true;
                     }
@@ -222,22 +779,103 @@ impl<DP: DependencyProvider> State<DP> {
         }
     }
 
+    /// Record that the dependencies of a version of a package, in the `conflict` incompatibility,
+    /// conflict with the partial solution, so that version was not decided on.
+    pub(crate) fn dependency_conflict(&mut self, package: Id<DP::P>, conflict: IncompDpId<DP>) {
+        self.package_statistics
+            .entry(package)
+            .or_default()
+            .dependencies_affected += 1;
+        for (p, _) in self.incompatibility_store[conflict].iter() {
+            if p != package {
+                self.package_statistics
+                    .entry(p)
+                    .or_default()
+                    .dependencies_culprit += 1;
+                self.partial_solution.outdate_priority(p);
+            }
+        }
+    }
+
+    /// Start keeping the decisions of the partial solution
+    /// that went the furthest before a conflict, see [State::best_decisions].
+    pub(crate) fn track_best_decisions(&mut self) {
+        self.best_decisions.get_or_insert_with(Map::default);
+    }
+
+    /// The most decisions that were consistent when a conflict happened,
+    /// when [tracked](State::track_best_decisions).
+    ///
+    /// The last decision before a conflict led to it, so it is not part of them.
+    pub(crate) fn best_decisions(&self) -> Option<&SelectedDependencies<DP>> {
+        self.best_decisions.as_ref()
+    }
+
+    fn update_best_decisions(&mut self) {
+        let Some(best) = &mut self.best_decisions else {
+            return;
+        };
+        let consistent =
+            (self.partial_solution.current_decision_level().0 as usize).saturating_sub(1);
+        if consistent > best.len() {
+            *best = self
+                .partial_solution
+                .decisions()
+                .take(consistent)
+                .map(|(p, v)| (self.package_store[p].clone(), v.clone()))
+                .collect();
+        }
+    }
+
+    /// Conflict resolution never backtracks below this decision level.
+    ///
+    /// When solving the dependencies of a root package,
+    /// the decision on that package is made at level 1 and is kept.
+    fn lowest_backtrack_level(&self) -> DecisionLevel {
+        match self.root {
+            Some(_) => DecisionLevel(1),
+            None => DecisionLevel(0),
+        }
+    }
+
     /// Backtracking.
     fn backtrack(
         &mut self,
         incompat: IncompDpId<DP>,
         incompat_changed: bool,
         decision_level: DecisionLevel,
+        observer: &impl Observer<DP::P, DP::VS>,
     ) {
         self.partial_solution.backtrack(decision_level);
+        self.stats.backtracks += 1;
+        observer.backtrack(decision_level.0);
         // Remove contradicted incompatibilities that depend on decisions we just backtracked away.
         self.contradicted_incompatibilities
             .retain(|_, dl| *dl <= decision_level);
         if incompat_changed {
+            observer.learned(self.incompatibility_store[incompat].as_terms(&self.package_store));
             self.merge_incompatibility(incompat);
+            self.stats.learned_incompatibilities += 1;
         }
     }
 
+    /// Backtrack to the lowest decision level, keeping the learned incompatibilities.
+    ///
+    /// Returns false if there was no decision to undo.
+    pub(crate) fn restart(&mut self, observer: &impl Observer<DP::P, DP::VS>) -> bool {
+        let decision_level = self.lowest_backtrack_level();
+        if self.partial_solution.current_decision_level() <= decision_level {
+            return false;
+        }
+        self.partial_solution.backtrack(decision_level);
+        self.stats.backtracks += 1;
+        self.stats.restarts += 1;
+        observer.backtrack(decision_level.0);
+        self.contradicted_incompatibilities
+            .retain(|_, dl| *dl <= decision_level);
+        true
+    }
+
     /// Add this incompatibility into the set of all incompatibilities.
     ///
     /// PubGrub collapses identical dependencies from adjacent package versions
@@ -256,10 +894,7 @@ impl<DP: DependencyProvider> State<DP> {
     fn merge_incompatibility(&mut self, mut id: IncompDpId<DP>) {
         if let Some((p1, p2)) = self.incompatibility_store[id].as_dependency() {
             // If we are a dependency, there's a good chance we can be merged with a previous dependency
-            let deps_lookup = self
-                .merged_dependencies
-                .entry((p1.clone(), p2.clone()))
-                .or_default();
+            let deps_lookup = self.merged_dependencies.entry((p1, p2)).or_default();
             if let Some((past, merged)) = deps_lookup.as_mut_slice().iter_mut().find_map(|past| {
                 self.incompatibility_store[id]
                     .merge_dependents(&self.incompatibility_store[*past])
@@ -268,7 +903,7 @@ impl<DP: DependencyProvider> State<DP> {
                 let new = self.incompatibility_store.alloc(merged);
                 for (pkg, _) in self.incompatibility_store[new].iter() {
                     self.incompatibilities
-                        .entry(pkg.clone())
+                        .entry(pkg)
                         .or_default()
                         .retain(|id| id != past);
                 }
@@ -282,15 +917,162 @@ impl<DP: DependencyProvider> State<DP> {
             if cfg!(debug_assertions) {
                 assert_ne!(term, &crate::term::Term::any());
             }
-            self.incompatibilities
-                .entry(pkg.clone())
-                .or_default()
-                .push(id);
+            self.incompatibilities.entry(pkg).or_default().push(id);
+        }
+        self.watch(id);
+    }
+
+    /// Choose the two terms watched by a new incompatibility with more than two terms.
+    ///
+    /// The terms that are not satisfied by the partial solution are preferred,
+    /// then the ones satisfied at the highest decision levels.
+    fn watch(&mut self, id: IncompDpId<DP>) {
+        let incompat = &self.incompatibility_store[id];
+        if incompat.len() <= 2 {
+            return;
+        }
+        let rank = |(package, term)| {
+            let rank = match self.partial_solution.satisfier_level(package, term) {
+                Some(level) => level.0,
+                None => u32::MAX,
+            };
+            (rank, package)
+        };
+        let mut terms = incompat.iter().map(rank);
+        let mut first = terms.next().unwrap();
+        let mut second = None;
+        for term in terms {
+            if term.0 > first.0 {
+                second = Some(first);
+                first = term;
+            } else if second.is_none_or(|second| term.0 > second.0) {
+                second = Some(term);
+            }
+        }
+        let (first, second) = (first.1, second.unwrap_or(first).1);
+        self.watchers.entry(first).or_default().push(id);
+        if second != first {
+            self.watchers.entry(second).or_default().push(id);
+        }
+        let index = id.into_raw();
+        if self.watched.len() <= index {
+            self.watched.resize(index + 1, None);
         }
+        self.watched[index] = Some((first, second));
     }
 
     // Error reporting #########################################################
 
+    /// Explain why the partial solution excludes a version of a package,
+    /// or [None] if nothing excludes it.
+    pub(crate) fn explain_exclusion(
+        &self,
+        package: &DP::P,
+        version: &DP::V,
+    ) -> Option<DerivationTree<DP::P, DP::VS, DP::M>> {
+        let package = self.package_store.get_id(package)?;
+        let (_, cause) = self.partial_solution.excluded_by(package, version)?;
+        Some(self.build_derivation_tree(cause))
+    }
+
+    /// The decisions and derivations that led to the decision on a package,
+    /// in the order they were made, or [None] if that package is not decided on.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn selection_steps(
+        &self,
+        package: &DP::P,
+    ) -> Option<Vec<SelectionStep<DP::P, DP::VS, DP::M>>> {
+        let package = self.package_store.get_id(package)?;
+        self.partial_solution.decision(package)?;
+        let steps = self
+            .assignments_behind(package, u32::MAX)
+            .into_iter()
+            .map(|(_, package, assignment)| match assignment {
+                Assignment::Decision(version) => SelectionStep::Decision {
+                    package: self.package_store[package].clone(),
+                    version: version.clone(),
+                },
+                Assignment::Derivation(cause) => SelectionStep::Derivation {
+                    package: self.package_store[package].clone(),
+                    term: self.incompatibility_store[cause]
+                        .get(package)
+                        .unwrap()
+                        .negate(),
+                    cause: self.build_derivation_tree(cause),
+                },
+            })
+            .collect();
+        Some(steps)
+    }
+
+    /// The locked packages behind the exclusion of a version of a package by the partial solution,
+    /// through the assignments that led to it and the incompatibilities they were derived from.
+    pub(crate) fn locks_excluding(&self, package: &DP::P, version: &DP::V) -> Vec<DP::P> {
+        let Some(package) = self.package_store.get_id(package) else {
+            return Vec::new();
+        };
+        let Some((global_index, _)) = self.partial_solution.excluded_by(package, version) else {
+            return Vec::new();
+        };
+        let mut locked = Vec::new();
+        let mut seen = Set::new();
+        for (_, _, assignment) in self.assignments_behind(package, global_index + 1) {
+            let Assignment::Derivation(cause) = assignment else {
+                continue;
+            };
+            let mut stack = vec![cause];
+            while let Some(id) = stack.pop() {
+                if !seen.insert(id) {
+                    continue;
+                }
+                let incompat = &self.incompatibility_store[id];
+                if let Some(package) = incompat.as_locked() {
+                    let package = &self.package_store[package];
+                    if !locked.contains(package) {
+                        locked.push(package.clone());
+                    }
+                }
+                if let Some((id1, id2)) = incompat.causes() {
+                    stack.push(id1);
+                    stack.push(id2);
+                }
+            }
+        }
+        locked
+    }
+
+    /// The assignments of a package made before the global index `before`,
+    /// and the assignments of the other packages of their causes
+    /// made before the derivation they explain, transitively,
+    /// with their global index, in the order they were made.
+    #[allow(clippy::type_complexity)]
+    fn assignments_behind(
+        &self,
+        package: Id<DP::P>,
+        before: u32,
+    ) -> Vec<(u32, Id<DP::P>, Assignment<'_, DP>)> {
+        let mut seen = Set::new();
+        let mut assignments = Vec::new();
+        let mut stack = vec![(package, before)];
+        while let Some((package, before)) = stack.pop() {
+            for (global_index, assignment) in self.partial_solution.assignments(package) {
+                if global_index >= before || !seen.insert(global_index) {
+                    continue;
+                }
+                if let Assignment::Derivation(cause) = assignment {
+                    for (other, _) in self.incompatibility_store[cause].iter() {
+                        if other != package {
+                            stack.push((other, global_index));
+                        }
+                    }
+                }
+                assignments.push((global_index, package, assignment));
+            }
+        }
+        assignments.sort_unstable_by_key(|(global_index, _, _)| *global_index);
+        assignments
+    }
+
     fn build_derivation_tree(
         &self,
         incompat: IncompDpId<DP>,
@@ -320,6 +1102,7 @@ impl<DP: DependencyProvider> State<DP> {
                 id,
                 &shared_ids,
                 &self.incompatibility_store,
+                &self.package_store,
                 &precomputed,
             );
             precomputed.insert(id, Arc::new(tree));
@@ -328,3 +1111,15 @@ impl<DP: DependencyProvider> State<DP> {
         Arc::into_inner(precomputed.remove(&incompat).unwrap()).unwrap()
     }
 }
+
+/// What unit propagation learns from an incompatibility watching a changed package.
+enum WatchUpdate<P> {
+    /// Nothing to do, the incompatibility keeps watching the package.
+    Kept,
+    /// The incompatibility does not watch the package anymore.
+    Moved,
+    /// The incompatibility is satisfied by the partial solution.
+    Conflict,
+    /// All the terms of the incompatibility are satisfied but the one of this package.
+    AlmostSatisfied(Id<P>),
+}
@@ -3,13 +3,13 @@
 //! An incompatibility is a set of terms for different packages
 //! that should never be satisfied all together.
 
-use std::fmt::{self, Debug, Display};
+use std::fmt::{Debug, Display};
 use std::sync::Arc;
 
-use crate::internal::{Arena, Id, SmallMap};
+use crate::internal::{Arena, HashArena, Id, SmallMap};
 use crate::{
-    term, DefaultStringReportFormatter, DependencyProvider, DerivationTree, Derived, External, Map,
-    Package, ReportFormatter, Set, Term, VersionSet,
+    term, DefaultStringReportFormatter, DerivationTree, Derived, External, IncompatibilityTerms,
+    Map, Package, ProviderTypes, ReportFormatter, Set, Term, VersionSet,
 };
 
 /// An incompatibility is a set of terms for different packages
@@ -24,23 +24,23 @@ use crate::{
 /// Therefore, the set `{ A = 1, not B = 2 }` is an incompatibility,
 /// defined from dependencies of A at version 1.
 ///
+/// Packages are referred to by their [Id] in the package store of the solver,
+/// the package itself is only needed to report the incompatibility.
+///
 /// Incompatibilities can also be derived from two other incompatibilities
 /// during conflict resolution. More about all this in
 /// [PubGrub documentation](https://github.com/dart-lang/pub/blob/master/doc/solver.md#incompatibility).
 #[derive(Debug, Clone)]
 pub(crate) struct Incompatibility<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
-    package_terms: SmallMap<P, Term<VS>>,
+    package_terms: SmallMap<Id<P>, Term<VS>>,
     kind: Kind<P, VS, M>,
 }
 
 /// Type alias of unique identifiers for incompatibilities.
 pub(crate) type IncompId<P, VS, M> = Id<Incompatibility<P, VS, M>>;
 
-pub(crate) type IncompDpId<DP> = IncompId<
-    <DP as DependencyProvider>::P,
-    <DP as DependencyProvider>::VS,
-    <DP as DependencyProvider>::M,
->;
+pub(crate) type IncompDpId<DP> =
+    IncompId<<DP as ProviderTypes>::P, <DP as ProviderTypes>::VS, <DP as ProviderTypes>::M>;
 
 #[derive(Debug, Clone)]
 enum Kind<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
@@ -48,12 +48,21 @@ enum Kind<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
     ///
     /// This incompatibility drives the resolution, it requires that we pick the (virtual) root
     /// packages.
-    NotRoot(P, VS::V),
+    NotRoot(Id<P>, VS::V),
+    /// Initial incompatibility requiring a version of a package in the given set.
+    ///
+    /// These replace [NotRoot](Kind::NotRoot) when resolving a set of requirements
+    /// instead of the dependencies of a root package.
+    Requirement(Id<P>, VS),
+    /// Initial incompatibility forbidding a package to be selected in the given set.
+    Exclusion(Id<P>, VS),
+    /// Initial incompatibility keeping a package at its locked version, if it is selected.
+    Locked(Id<P>, VS::V),
     /// There are no versions in the given range for this package.
     ///
     /// This incompatibility is used when we tried all versions in a range and no version
     /// worked, so we have to backtrack
-    NoVersions(P, VS),
+    NoVersions(Id<P>, VS),
     /// Incompatibility coming from the dependencies of a given package.
     ///
     /// If a@1 depends on b>=1,<2, we create an incompatibility with terms `{a 1, b <1,>=2}` with
@@ -61,7 +70,25 @@ enum Kind<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
     ///
     /// We can merge multiple dependents with the same version. For example, if a@1 depends on b and
     /// a@2 depends on b, we can say instead a@1||2 depends on b.
-    FromDependencyOf(P, VS, P, VS),
+    FromDependencyOf(Id<P>, VS, Id<P>, VS),
+    /// Incompatibility coming from a constraint of a given package on another one,
+    /// which only applies if that other package is selected.
+    ///
+    /// If a@1 constrains b to >=2, we create an incompatibility with terms `{a 1, b <2}`
+    /// (both positive) with kind `FromConstraintOf(a, 1, b, >=2)`.
+    FromConstraintOf(Id<P>, VS, Id<P>, VS),
+    /// Incompatibility coming from a dependency of a given package on any of several packages.
+    ///
+    /// If a@1 depends on b>=2 or c, we create an incompatibility with terms `{a 1, b <2, not c}`
+    /// with kind `FromAnyDependencyOf(a, 1, [(b, >=2), (c, *)])`.
+    /// The alternatives are kept in the order of preference they were given in.
+    FromAnyDependencyOf(Id<P>, VS, Vec<(Id<P>, VS)>),
+    /// Incompatibility coming from a package providing a version of a virtual package.
+    ///
+    /// If a@1 provides the version 2 of the virtual package b, we create an incompatibility
+    /// with terms `{a 1, not b 2}` with kind `Provides(a, 1, b, 2)`:
+    /// the virtual package has the version its selected provider gives it.
+    Provides(Id<P>, VS, Id<P>, VS::V),
     /// Derived from two causes. Stores cause ids.
     ///
     /// For example, if a -> b and b -> c, we can derive a -> c.
@@ -71,7 +98,10 @@ enum Kind<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
     /// Examples:
     /// * The version would require building the package, but builds are disabled.
     /// * The package is not available in the cache, but internet access has been disabled.
-    Custom(P, VS, M),
+    Custom(Id<P>, VS, M),
+    /// A combination of versions the search does not need to explore anymore,
+    /// for example because it is a solution that was already found.
+    Pruned,
 }
 
 /// A Relation describes how a set of terms can be compared to an incompatibility.
@@ -83,84 +113,290 @@ pub(crate) enum Relation<P: Package> {
     Satisfied,
     /// We say that S contradicts I
     /// if S contradicts at least one term in I.
-    Contradicted(P),
+    Contradicted(Id<P>),
     /// If S satisfies all but one of I's terms and is inconclusive for the remaining term,
     /// we say S "almost satisfies" I and we call the remaining term the "unsatisfied term".
-    AlmostSatisfied(P),
+    AlmostSatisfied(Id<P>),
     /// Otherwise, we say that their relation is inconclusive.
     Inconclusive,
 }
 
 impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibility<P, VS, M> {
     /// Create the initial "not Root" incompatibility.
-    pub(crate) fn not_root(package: P, version: VS::V) -> Self {
+    pub(crate) fn not_root(package: Id<P>, version: VS::V) -> Self {
         Self {
             package_terms: SmallMap::One([(
-                package.clone(),
+                package,
                 Term::Negative(VS::singleton(version.clone())),
             )]),
             kind: Kind::NotRoot(package, version),
         }
     }
 
+    /// Create the initial incompatibility requiring a version of a package in the given set.
+    ///
+    /// If the set is empty, the incompatibility has no term left:
+    /// the requirements are impossible to satisfy.
+    pub(crate) fn requirement(package: Id<P>, set: VS) -> Self {
+        Self {
+            package_terms: if set == VS::empty() {
+                SmallMap::Empty
+            } else {
+                SmallMap::One([(package, Term::Negative(set.clone()))])
+            },
+            kind: Kind::Requirement(package, set),
+        }
+    }
+
+    /// Create the initial incompatibility forbidding a package to be selected in the given set.
+    pub(crate) fn exclusion(package: Id<P>, set: VS) -> Self {
+        Self {
+            package_terms: SmallMap::One([(package, Term::Positive(set.clone()))]),
+            kind: Kind::Exclusion(package, set),
+        }
+    }
+
+    /// Create the initial incompatibility keeping a package at its locked version, if it is selected.
+    pub(crate) fn locked(package: Id<P>, version: VS::V) -> Self {
+        let set = VS::singleton(version.clone()).complement();
+        Self {
+            package_terms: SmallMap::One([(package, Term::Positive(set))]),
+            kind: Kind::Locked(package, version),
+        }
+    }
+
     /// Create an incompatibility to remember that a given set does not contain any version.
-    pub(crate) fn no_versions(package: P, term: Term<VS>) -> Self {
+    pub(crate) fn no_versions(package: Id<P>, term: Term<VS>) -> Self {
         let set = match &term {
             Term::Positive(r) => r.clone(),
             Term::Negative(_) => panic!("No version should have a positive term"),
         };
         Self {
-            package_terms: SmallMap::One([(package.clone(), term)]),
+            package_terms: SmallMap::One([(package, term)]),
             kind: Kind::NoVersions(package, set),
         }
     }
 
     /// Create an incompatibility for a reason outside pubgrub.
     #[allow(dead_code)] // Used by uv
-    pub(crate) fn custom_term(package: P, term: Term<VS>, metadata: M) -> Self {
+    pub(crate) fn custom_term(package: Id<P>, term: Term<VS>, metadata: M) -> Self {
         let set = match &term {
             Term::Positive(r) => r.clone(),
             Term::Negative(_) => panic!("No version should have a positive term"),
         };
         Self {
-            package_terms: SmallMap::One([(package.clone(), term)]),
+            package_terms: SmallMap::One([(package, term)]),
             kind: Kind::Custom(package, set, metadata),
         }
     }
 
     /// Create an incompatibility for a reason outside pubgrub.
-    pub(crate) fn custom_version(package: P, version: VS::V, metadata: M) -> Self {
+    pub(crate) fn custom_version(package: Id<P>, version: VS::V, metadata: M) -> Self {
         let set = VS::singleton(version);
         let term = Term::Positive(set.clone());
         Self {
-            package_terms: SmallMap::One([(package.clone(), term)]),
+            package_terms: SmallMap::One([(package, term)]),
             kind: Kind::Custom(package, set, metadata),
         }
     }
 
+    /// Create an incompatibility forbidding a combination of versions to be selected together.
+    pub(crate) fn pruned(versions: impl IntoIterator<Item = (Id<P>, VS::V)>) -> Self {
+        let mut package_terms = SmallMap::Empty;
+        for (package, version) in versions {
+            package_terms.insert(package, Term::Positive(VS::singleton(version)));
+        }
+        Self {
+            package_terms,
+            kind: Kind::Pruned,
+        }
+    }
+
     /// Build an incompatibility from a given dependency.
-    pub(crate) fn from_dependency(package: P, versions: VS, dep: (P, VS)) -> Self {
+    pub(crate) fn from_dependency(package: Id<P>, versions: VS, dep: (Id<P>, VS)) -> Self {
         let (p2, set2) = dep;
         Self {
             package_terms: if set2 == VS::empty() {
-                SmallMap::One([(package.clone(), Term::Positive(versions.clone()))])
+                SmallMap::One([(package, Term::Positive(versions.clone()))])
             } else {
                 SmallMap::Two([
-                    (package.clone(), Term::Positive(versions.clone())),
-                    (p2.clone(), Term::Negative(set2.clone())),
+                    (package, Term::Positive(versions.clone())),
+                    (p2, Term::Negative(set2.clone())),
                 ])
             },
             kind: Kind::FromDependencyOf(package, versions, p2, set2),
         }
     }
 
-    pub(crate) fn as_dependency(&self) -> Option<(&P, &P)> {
+    /// Build an incompatibility from a constraint on a package that may not be selected.
+    pub(crate) fn from_constraint(package: Id<P>, versions: VS, constraint: (Id<P>, VS)) -> Self {
+        let (p2, set2) = constraint;
+        Self {
+            package_terms: SmallMap::Two([
+                (package, Term::Positive(versions.clone())),
+                (p2, Term::Positive(set2.complement())),
+            ]),
+            kind: Kind::FromConstraintOf(package, versions, p2, set2),
+        }
+    }
+
+    /// Build an incompatibility from a dependency on any of several packages.
+    ///
+    /// Alternatives on the same package are merged, and empty ones are left out:
+    /// without any alternative left, the package versions are unusable.
+    pub(crate) fn from_any_dependency(
+        package: Id<P>,
+        versions: VS,
+        alternatives: Vec<(Id<P>, VS)>,
+    ) -> Self {
+        let mut package_terms = SmallMap::One([(package, Term::Positive(versions.clone()))]);
+        for (p2, set2) in &alternatives {
+            let term = match package_terms.get(p2) {
+                Some(term) => term.intersection(&Term::Negative(set2.clone())),
+                None => Term::Negative(set2.clone()),
+            };
+            if term == Term::any() {
+                package_terms.remove(p2);
+            } else {
+                package_terms.insert(*p2, term);
+            }
+        }
+        Self {
+            package_terms,
+            kind: Kind::FromAnyDependencyOf(package, versions, alternatives),
+        }
+    }
+
+    /// Build an incompatibility from a package version providing a version of a virtual package.
+    pub(crate) fn provides(package: Id<P>, versions: VS, provided: (Id<P>, VS::V)) -> Self {
+        let (p2, v2) = provided;
+        Self {
+            package_terms: SmallMap::Two([
+                (package, Term::Positive(versions.clone())),
+                (p2, Term::Negative(VS::singleton(v2.clone()))),
+            ]),
+            kind: Kind::Provides(package, versions, p2, v2),
+        }
+    }
+
+    pub(crate) fn as_dependency(&self) -> Option<(Id<P>, Id<P>)> {
+        match &self.kind {
+            Kind::FromDependencyOf(p1, _, p2, _) => Some((*p1, *p2)),
+            _ => None,
+        }
+    }
+
+    /// The dependent package and versions, and the dependency with the versions they require.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn as_dependency_ranges(&self) -> Option<(Id<P>, &VS, Id<P>, &VS)> {
+        match &self.kind {
+            Kind::FromDependencyOf(p1, set1, p2, set2) => Some((*p1, set1, *p2, set2)),
+            _ => None,
+        }
+    }
+
+    /// The dependent package and versions, and the alternatives of a dependency
+    /// on any of several packages.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn as_any_dependency(&self) -> Option<(Id<P>, &VS, &[(Id<P>, VS)])> {
+        match &self.kind {
+            Kind::FromAnyDependencyOf(p1, set1, alternatives) => Some((*p1, set1, alternatives)),
+            _ => None,
+        }
+    }
+
+    /// The package kept at its locked version.
+    pub(crate) fn as_locked(&self) -> Option<Id<P>> {
+        match &self.kind {
+            Kind::Locked(p, _) => Some(*p),
+            _ => None,
+        }
+    }
+
+    /// The providing package and versions, and the virtual package and version they provide.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn as_provides(&self) -> Option<(Id<P>, &VS, Id<P>, &VS::V)> {
         match &self.kind {
-            Kind::FromDependencyOf(p1, _, p2, _) => Some((p1, p2)),
+            Kind::Provides(p1, set1, p2, v2) => Some((*p1, set1, *p2, v2)),
             _ => None,
         }
     }
 
+    /// Whether this incompatibility only states facts from the dependency provider.
+    ///
+    /// It is not the case for the initial incompatibilities of a resolution,
+    /// coming from its root package, requirements, exclusions or locks,
+    /// nor for pruned solutions.
+    /// A derived incompatibility is not an external fact, see its causes instead.
+    pub(crate) fn is_provider_fact(&self) -> bool {
+        match self.kind {
+            Kind::NotRoot(..)
+            | Kind::Requirement(..)
+            | Kind::Exclusion(..)
+            | Kind::Locked(..)
+            | Kind::Pruned
+            | Kind::DerivedFrom(..) => false,
+            Kind::NoVersions(..)
+            | Kind::FromDependencyOf(..)
+            | Kind::FromConstraintOf(..)
+            | Kind::FromAnyDependencyOf(..)
+            | Kind::Provides(..)
+            | Kind::Custom(..) => true,
+        }
+    }
+
+    /// The same incompatibility, with its packages and causes
+    /// given by their ids in other stores.
+    pub(crate) fn translate(
+        &self,
+        mut package: impl FnMut(Id<P>) -> Id<P>,
+        cause: impl Fn(Id<Self>) -> Id<Self>,
+    ) -> Self {
+        let mut package_terms = SmallMap::default();
+        for (p, term) in self.package_terms.iter() {
+            package_terms.insert(package(*p), term.clone());
+        }
+        fn packages<P, VS: Clone>(
+            alternatives: &[(Id<P>, VS)],
+            package: &mut impl FnMut(Id<P>) -> Id<P>,
+        ) -> Vec<(Id<P>, VS)> {
+            alternatives
+                .iter()
+                .map(|(p, set)| (package(*p), set.clone()))
+                .collect()
+        }
+        let kind = match &self.kind {
+            Kind::NotRoot(p, version) => Kind::NotRoot(package(*p), version.clone()),
+            Kind::Requirement(p, set) => Kind::Requirement(package(*p), set.clone()),
+            Kind::Exclusion(p, set) => Kind::Exclusion(package(*p), set.clone()),
+            Kind::Locked(p, version) => Kind::Locked(package(*p), version.clone()),
+            Kind::NoVersions(p, set) => Kind::NoVersions(package(*p), set.clone()),
+            Kind::FromDependencyOf(p1, set1, p2, set2) => {
+                Kind::FromDependencyOf(package(*p1), set1.clone(), package(*p2), set2.clone())
+            }
+            Kind::FromConstraintOf(p1, set1, p2, set2) => {
+                Kind::FromConstraintOf(package(*p1), set1.clone(), package(*p2), set2.clone())
+            }
+            Kind::FromAnyDependencyOf(p, set, alternatives) => Kind::FromAnyDependencyOf(
+                package(*p),
+                set.clone(),
+                packages(alternatives, &mut package),
+            ),
+            Kind::Provides(p1, set1, p2, v2) => {
+                Kind::Provides(package(*p1), set1.clone(), package(*p2), v2.clone())
+            }
+            Kind::DerivedFrom(id1, id2) => Kind::DerivedFrom(cause(*id1), cause(*id2)),
+            Kind::Custom(p, set, metadata) => {
+                Kind::Custom(package(*p), set.clone(), metadata.clone())
+            }
+            Kind::Pruned => Kind::Pruned,
+        };
+        Self {
+            package_terms,
+            kind,
+        }
+    }
+
     /// Merge dependant versions with the same dependency.
     ///
     /// When multiple versions of a package depend on the same range of another package,
@@ -187,13 +423,13 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibilit
             return None;
         }
         return Some(Self::from_dependency(
-            p1.clone(),
+            p1,
             self.get(p1)
                 .unwrap()
                 .unwrap_positive()
                 .union(other.get(p1).unwrap().unwrap_positive()), // It is safe to `simplify` here
             (
-                p2.clone(),
+                p2,
                 dep_term.map_or(VS::empty(), |v| v.unwrap_negative().clone()),
             ),
         ));
@@ -203,23 +439,23 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibilit
     pub(crate) fn prior_cause(
         incompat: Id<Self>,
         satisfier_cause: Id<Self>,
-        package: &P,
+        package: Id<P>,
         incompatibility_store: &Arena<Self>,
     ) -> Self {
         let kind = Kind::DerivedFrom(incompat, satisfier_cause);
         // Optimization to avoid cloning and dropping t1
         let (t1, mut package_terms) = incompatibility_store[incompat]
             .package_terms
-            .split_one(package)
+            .split_one(&package)
             .unwrap();
         let satisfier_cause_terms = &incompatibility_store[satisfier_cause].package_terms;
         package_terms.merge(
-            satisfier_cause_terms.iter().filter(|(p, _)| p != &package),
+            satisfier_cause_terms.iter().filter(|(p, _)| p != &&package),
             |t1, t2| Some(t1.intersection(t2)),
         );
-        let term = t1.union(satisfier_cause_terms.get(package).unwrap());
+        let term = t1.union(satisfier_cause_terms.get(&package).unwrap());
         if term != Term::any() {
-            package_terms.insert(package.clone(), term);
+            package_terms.insert(package, term);
         }
         Self {
             package_terms,
@@ -229,25 +465,52 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibilit
 
     /// Check if an incompatibility should mark the end of the algorithm
     /// because it satisfies the root package.
-    pub(crate) fn is_terminal(&self, root_package: &P, root_version: &VS::V) -> bool {
+    ///
+    /// Without a root package, only the empty incompatibility is terminal.
+    pub(crate) fn is_terminal(&self, root: Option<&(Id<P>, VS::V)>) -> bool {
         if self.package_terms.len() == 0 {
             true
         } else if self.package_terms.len() > 1 {
             false
-        } else {
+        } else if let Some((root_package, root_version)) = root {
             let (package, term) = self.package_terms.iter().next().unwrap();
             (package == root_package) && term.contains(root_version)
+        } else {
+            false
         }
     }
 
+    /// The terms of the incompatibility, by package.
+    pub(crate) fn as_map(&self, package_store: &HashArena<P>) -> Map<P, Term<VS>> {
+        self.package_terms
+            .iter()
+            .map(|(package, term)| (package_store[*package].clone(), term.clone()))
+            .collect()
+    }
+
+    /// The terms of the incompatibility, looking up their package on demand.
+    pub(crate) fn as_terms<'a>(
+        &'a self,
+        package_store: &'a HashArena<P>,
+    ) -> IncompatibilityTerms<'a, P, VS> {
+        IncompatibilityTerms::new(&self.package_terms, package_store)
+    }
+
     /// Get the term related to a given package (if it exists).
-    pub(crate) fn get(&self, package: &P) -> Option<&Term<VS>> {
-        self.package_terms.get(package)
+    pub(crate) fn get(&self, package: Id<P>) -> Option<&Term<VS>> {
+        self.package_terms.get(&package)
+    }
+
+    /// Number of terms of the incompatibility.
+    pub(crate) fn len(&self) -> usize {
+        self.package_terms.len()
     }
 
     /// Iterate over packages.
-    pub(crate) fn iter(&self) -> impl Iterator<Item = (&P, &Term<VS>)> {
-        self.package_terms.iter()
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Id<P>, &Term<VS>)> {
+        self.package_terms
+            .iter()
+            .map(|(package, term)| (*package, term))
     }
 
     // Reporting ###############################################################
@@ -265,12 +528,20 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibilit
         self_id: Id<Self>,
         shared_ids: &Set<Id<Self>>,
         store: &Arena<Self>,
+        package_store: &HashArena<P>,
         precomputed: &Map<Id<Self>, Arc<DerivationTree<P, VS, M>>>,
     ) -> DerivationTree<P, VS, M> {
+        let package = |id: Id<P>| package_store[id].clone();
+        let packages = |alternatives: Vec<(Id<P>, VS)>| {
+            alternatives
+                .into_iter()
+                .map(|(p, set)| (package(p), set))
+                .collect()
+        };
         match store[self_id].kind.clone() {
             Kind::DerivedFrom(id1, id2) => {
                 let derived = Derived {
-                    terms: store[self_id].package_terms.as_map(),
+                    terms: store[self_id].as_map(package_store),
                     shared_id: shared_ids.get(&self_id).map(|id| id.into_raw()),
                     cause1: precomputed
                         .get(&id1)
@@ -283,40 +554,70 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Incompatibilit
                 };
                 DerivationTree::Derived(derived)
             }
-            Kind::NotRoot(package, version) => {
-                DerivationTree::External(External::NotRoot(package, version))
+            Kind::NotRoot(p, version) => {
+                DerivationTree::External(External::NotRoot(package(p), version))
+            }
+            Kind::Requirement(p, set) => {
+                DerivationTree::External(External::Requirement(package(p), set))
+            }
+            Kind::Exclusion(p, set) => {
+                DerivationTree::External(External::Exclusion(package(p), set))
             }
-            Kind::NoVersions(package, set) => {
-                DerivationTree::External(External::NoVersions(package.clone(), set.clone()))
+            Kind::Locked(p, version) => {
+                DerivationTree::External(External::Locked(package(p), version))
             }
-            Kind::FromDependencyOf(package, set, dep_package, dep_set) => {
-                DerivationTree::External(External::FromDependencyOf(
-                    package.clone(),
-                    set.clone(),
-                    dep_package.clone(),
-                    dep_set.clone(),
+            Kind::NoVersions(p, set) => {
+                DerivationTree::External(External::NoVersions(package(p), set))
+            }
+            Kind::FromDependencyOf(p, set, dep_package, dep_set) => DerivationTree::External(
+                External::FromDependencyOf(package(p), set, package(dep_package), dep_set),
+            ),
+            Kind::FromConstraintOf(p, set, constrained_package, constrained_set) => {
+                DerivationTree::External(External::FromConstraintOf(
+                    package(p),
+                    set,
+                    package(constrained_package),
+                    constrained_set,
                 ))
             }
-            Kind::Custom(package, set, metadata) => DerivationTree::External(External::Custom(
-                package.clone(),
-                set.clone(),
-                metadata.clone(),
+            Kind::FromAnyDependencyOf(p, set, alternatives) => DerivationTree::External(
+                External::FromAnyDependencyOf(package(p), set, packages(alternatives)),
+            ),
+            Kind::Provides(p, set, virtual_package, version) => DerivationTree::External(
+                External::Provides(package(p), set, package(virtual_package), version),
+            ),
+            Kind::Custom(p, set, metadata) => {
+                DerivationTree::External(External::Custom(package(p), set, metadata))
+            }
+            Kind::Pruned => DerivationTree::External(External::Pruned(
+                store[self_id]
+                    .iter()
+                    .map(|(p, t)| (package(p), t.unwrap_positive().clone()))
+                    .collect(),
             )),
         }
     }
+
+    /// Display the terms of the incompatibility, with the packages from the package store.
+    pub(crate) fn display(&self, package_store: &HashArena<P>) -> String {
+        ReportFormatter::<P, VS, M>::format_terms(
+            &DefaultStringReportFormatter,
+            &self.as_map(package_store),
+        )
+    }
 }
 
 impl<'a, P: Package, VS: VersionSet + 'a, M: Eq + Clone + Debug + Display + 'a>
     Incompatibility<P, VS, M>
 {
     /// CF definition of Relation enum.
-    pub(crate) fn relation(&self, terms: impl Fn(&P) -> Option<&'a Term<VS>>) -> Relation<P> {
+    pub(crate) fn relation(&self, terms: impl Fn(Id<P>) -> Option<&'a Term<VS>>) -> Relation<P> {
         let mut relation = Relation::Satisfied;
-        for (package, incompat_term) in self.package_terms.iter() {
+        for (&package, incompat_term) in self.package_terms.iter() {
             match terms(package).map(|term| incompat_term.relation_with(term)) {
                 Some(term::Relation::Satisfied) => {}
                 Some(term::Relation::Contradicted) => {
-                    return Relation::Contradicted(package.clone());
+                    return Relation::Contradicted(package);
                 }
                 None | Some(term::Relation::Inconclusive) => {
                     // If a package is not present, the intersection is the same as [Term::any].
@@ -325,7 +626,7 @@ impl<'a, P: Package, VS: VersionSet + 'a, M: Eq + Clone + Debug + Display + 'a>
                     // but we systematically remove those from incompatibilities
                     // so we're safe on that front.
                     if relation == Relation::Satisfied {
-                        relation = Relation::AlmostSatisfied(package.clone());
+                        relation = Relation::AlmostSatisfied(package);
                     } else {
                         return Relation::Inconclusive;
                     }
@@ -336,21 +637,6 @@ impl<'a, P: Package, VS: VersionSet + 'a, M: Eq + Clone + Debug + Display + 'a>
     }
 }
 
-impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Display
-    for Incompatibility<P, VS, M>
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            ReportFormatter::<P, VS, M>::format_terms(
-                &DefaultStringReportFormatter,
-                &self.package_terms.as_map()
-            )
-        )
-    }
-}
-
 // TESTS #######################################################################
 
 #[cfg(test)]
@@ -372,23 +658,27 @@ pub(crate) mod tests {
         ///    { p1: t1, p3: t3 }
         #[test]
         fn rule_of_resolution(t1 in term_strat(), t2 in term_strat(), t3 in term_strat()) {
+            let mut package_store = HashArena::new();
+            let p1 = package_store.alloc("p1");
+            let p2 = package_store.alloc("p2");
+            let p3 = package_store.alloc("p3");
             let mut store = Arena::new();
             let i1 = store.alloc(Incompatibility {
-                package_terms: SmallMap::Two([("p1", t1.clone()), ("p2", t2.negate())]),
-                kind: Kind::<_, _, String>::FromDependencyOf("p1", Ranges::full(), "p2", Ranges::full())
+                package_terms: SmallMap::Two([(p1, t1.clone()), (p2, t2.negate())]),
+                kind: Kind::<_, _, String>::FromDependencyOf(p1, Ranges::full(), p2, Ranges::full())
             });
 
             let i2 = store.alloc(Incompatibility {
-                package_terms: SmallMap::Two([("p2", t2), ("p3", t3.clone())]),
-                kind: Kind::<_, _, String>::FromDependencyOf("p2", Ranges::full(), "p3", Ranges::full())
+                package_terms: SmallMap::Two([(p2, t2), (p3, t3.clone())]),
+                kind: Kind::<_, _, String>::FromDependencyOf(p2, Ranges::full(), p3, Ranges::full())
             });
 
             let mut i3 = Map::default();
             i3.insert("p1", t1);
             i3.insert("p3", t3);
 
-            let i_resolution = Incompatibility::prior_cause(i1, i2, &"p2", &store);
-            assert_eq!(i_resolution.package_terms.as_map(), i3);
+            let i_resolution = Incompatibility::prior_cause(i1, i2, p2, &store);
+            assert_eq!(i_resolution.as_map(&package_store), i3);
         }
 
     }
@@ -9,9 +9,9 @@ mod partial_solution;
 mod small_map;
 mod small_vec;
 
-pub(crate) use arena::{Arena, Id};
+pub(crate) use arena::{Arena, HashArena, Id};
 pub(crate) use core::State;
 pub(crate) use incompatibility::{IncompDpId, IncompId, Incompatibility, Relation};
-pub(crate) use partial_solution::{DecisionLevel, PartialSolution, SatisfierSearch};
+pub(crate) use partial_solution::{Assignment, DecisionLevel, PartialSolution, SatisfierSearch};
 pub(crate) use small_map::SmallMap;
 pub(crate) use small_vec::SmallVec;
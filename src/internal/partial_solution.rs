@@ -10,8 +10,10 @@ use priority_queue::PriorityQueue;
 use rustc_hash::FxHasher;
 
 use super::small_vec::SmallVec;
-use crate::internal::{Arena, IncompDpId, IncompId, Incompatibility, Relation, SmallMap};
-use crate::{DependencyProvider, Package, SelectedDependencies, Term, VersionSet};
+use crate::internal::{
+    Arena, HashArena, Id, IncompDpId, IncompId, Incompatibility, Relation, SmallMap,
+};
+use crate::{term, Package, ProviderTypes, SelectedDependencies, Term, VersionSet};
 
 type FnvIndexMap<K, V> = indexmap::IndexMap<K, V, BuildHasherDefault<FxHasher>>;
 
@@ -26,8 +28,8 @@ impl DecisionLevel {
 
 /// The partial solution contains all package assignments,
 /// organized by package and historically ordered.
-#[derive(Clone, Debug)]
-pub(crate) struct PartialSolution<DP: DependencyProvider> {
+#[derive(Debug)]
+pub(crate) struct PartialSolution<DP: ProviderTypes> {
     next_global_index: u32,
     current_decision_level: DecisionLevel,
     /// `package_assignments` is primarily a HashMap from a package to its
@@ -41,25 +43,42 @@ pub(crate) struct PartialSolution<DP: DependencyProvider> {
     ///    the last time `prioritize` has been called. The inverse is not necessarily true, some packages in the range
     ///    did not have a change. Within this range there is no sorting.
     #[allow(clippy::type_complexity)]
-    package_assignments: FnvIndexMap<DP::P, PackageAssignments<DP::P, DP::VS, DP::M>>,
+    package_assignments: FnvIndexMap<Id<DP::P>, PackageAssignments<DP::P, DP::VS, DP::M>>,
     /// `prioritized_potential_packages` is primarily a HashMap from a package with no desition and a positive assignment
     /// to its `Priority`. But, it also maintains a max heap of packages by `Priority` order.
     prioritized_potential_packages:
-        PriorityQueue<DP::P, DP::Priority, BuildHasherDefault<FxHasher>>,
+        PriorityQueue<Id<DP::P>, DP::Priority, BuildHasherDefault<FxHasher>>,
     changed_this_decision_level: usize,
+    /// Packages whose priority must be computed again even if their assignments did not change.
+    outdated_priorities: Vec<Id<DP::P>>,
     has_ever_backtracked: bool,
 }
 
-impl<DP: DependencyProvider> Display for PartialSolution<DP> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+// Implemented by hand so that the dependency provider does not need to be cloneable.
+impl<DP: ProviderTypes> Clone for PartialSolution<DP> {
+    fn clone(&self) -> Self {
+        Self {
+            next_global_index: self.next_global_index,
+            current_decision_level: self.current_decision_level,
+            package_assignments: self.package_assignments.clone(),
+            prioritized_potential_packages: self.prioritized_potential_packages.clone(),
+            changed_this_decision_level: self.changed_this_decision_level,
+            outdated_priorities: self.outdated_priorities.clone(),
+            has_ever_backtracked: self.has_ever_backtracked,
+        }
+    }
+}
+
+impl<DP: ProviderTypes> PartialSolution<DP> {
+    /// Display the partial solution, with the packages from the package store.
+    pub(crate) fn display(&self, package_store: &HashArena<DP::P>) -> String {
         let mut assignments: Vec<_> = self
             .package_assignments
             .iter()
-            .map(|(p, pa)| format!("{}: {}", p, pa))
+            .map(|(p, pa)| format!("{}: {}", package_store[*p], pa))
             .collect();
         assignments.sort();
-        write!(
-            f,
+        format!(
             "next_global_index: {}\ncurrent_decision_level: {:?}\npackage_assignments:\n{}",
             self.next_global_index,
             self.current_decision_level,
@@ -142,9 +161,17 @@ pub(crate) enum SatisfierSearch<P: Package, VS: VersionSet, M: Eq + Clone + Debu
     },
 }
 
-type SatisfiedMap<'i, P, VS, M> = SmallMap<&'i P, (Option<IncompId<P, VS, M>>, u32, DecisionLevel)>;
+/// An assignment of a package in the partial solution, from [PartialSolution::assignments].
+pub(crate) enum Assignment<'a, DP: ProviderTypes> {
+    /// The package was decided on at this version.
+    Decision(&'a DP::V),
+    /// A term was derived for the package from this incompatibility.
+    Derivation(IncompDpId<DP>),
+}
+
+type SatisfiedMap<P, VS, M> = SmallMap<Id<P>, (Option<IncompId<P, VS, M>>, u32, DecisionLevel)>;
 
-impl<DP: DependencyProvider> PartialSolution<DP> {
+impl<DP: ProviderTypes> PartialSolution<DP> {
     /// Initialize an empty PartialSolution.
     pub(crate) fn empty() -> Self {
         Self {
@@ -153,16 +180,20 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
             package_assignments: FnvIndexMap::default(),
             prioritized_potential_packages: PriorityQueue::default(),
             changed_this_decision_level: 0,
+            outdated_priorities: Vec::new(),
             has_ever_backtracked: false,
         }
     }
 
     /// Add a decision.
-    pub(crate) fn add_decision(&mut self, package: DP::P, version: DP::V) {
+    ///
+    /// The package usually has derivations already, except for an alternative
+    /// of a dependency on any of several packages, that no derivation requires.
+    pub(crate) fn add_decision(&mut self, package: Id<DP::P>, version: DP::V) {
         // Check that add_decision is never used in the wrong context.
         if cfg!(debug_assertions) {
             match self.package_assignments.get_mut(&package) {
-                None => panic!("Derivations must already exist"),
+                None => {}
                 Some(pa) => match &pa.assignments_intersection {
                     // Cannot be called when a decision has already been taken.
                     AssignmentsIntersection::Decision(_) => panic!("Already existing decision"),
@@ -170,7 +201,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
                     AssignmentsIntersection::Derivations(term) => {
                         debug_assert!(
                             term.contains(&version),
-                            "{}: {} was expected to be contained in {}",
+                            "{:?}: {} was expected to be contained in {}",
                             package,
                             version,
                             term,
@@ -185,10 +216,15 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
         }
         let new_idx = self.current_decision_level.0 as usize;
         self.current_decision_level = self.current_decision_level.increment();
-        let (old_idx, _, pa) = self
-            .package_assignments
-            .get_full_mut(&package)
-            .expect("Derivations must already exist");
+        let current_decision_level = self.current_decision_level;
+        let entry = self.package_assignments.entry(package);
+        let old_idx = entry.index();
+        let pa = entry.or_insert_with(|| PackageAssignments {
+            smallest_decision_level: current_decision_level,
+            highest_decision_level: current_decision_level,
+            dated_derivations: SmallVec::Empty,
+            assignments_intersection: AssignmentsIntersection::Derivations(Term::any()),
+        });
         pa.highest_decision_level = self.current_decision_level;
         pa.assignments_intersection = AssignmentsIntersection::Decision((
             self.next_global_index,
@@ -203,22 +239,24 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
     }
 
     /// Add a derivation.
+    ///
+    /// Returns the package with its updated term intersection.
     pub(crate) fn add_derivation(
         &mut self,
-        package: DP::P,
+        package: Id<DP::P>,
         cause: IncompDpId<DP>,
         store: &Arena<Incompatibility<DP::P, DP::VS, DP::M>>,
-    ) {
+    ) -> &Term<DP::VS> {
         use indexmap::map::Entry;
         let mut dated_derivation = DatedDerivation {
             global_index: self.next_global_index,
             decision_level: self.current_decision_level,
             cause,
-            accumulated_intersection: store[cause].get(&package).unwrap().negate(),
+            accumulated_intersection: store[cause].get(package).unwrap().negate(),
         };
         self.next_global_index += 1;
         let pa_last_index = self.package_assignments.len().saturating_sub(1);
-        match self.package_assignments.entry(package) {
+        let idx = match self.package_assignments.entry(package) {
             Entry::Occupied(mut occupied) => {
                 let idx = occupied.index();
                 let pa = occupied.get_mut();
@@ -240,6 +278,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
                     }
                 }
                 pa.dated_derivations.push(dated_derivation);
+                idx
             }
             Entry::Vacant(v) => {
                 let term = dated_derivation.accumulated_intersection.clone();
@@ -247,20 +286,24 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
                     self.changed_this_decision_level =
                         std::cmp::min(self.changed_this_decision_level, pa_last_index);
                 }
+                let idx = v.index();
                 v.insert(PackageAssignments {
                     smallest_decision_level: self.current_decision_level,
                     highest_decision_level: self.current_decision_level,
                     dated_derivations: SmallVec::One([dated_derivation]),
                     assignments_intersection: AssignmentsIntersection::Derivations(term),
                 });
+                idx
             }
-        }
+        };
+        let (_, pa) = self.package_assignments.get_index(idx).unwrap();
+        pa.assignments_intersection.term()
     }
 
     pub(crate) fn pick_highest_priority_pkg(
         &mut self,
-        prioritizer: impl Fn(&DP::P, &DP::VS) -> DP::Priority,
-    ) -> Option<DP::P> {
+        prioritizer: impl Fn(Id<DP::P>, &DP::VS) -> DP::Priority,
+    ) -> Option<Id<DP::P>> {
         let check_all = self.changed_this_decision_level
             == self.current_decision_level.0.saturating_sub(1) as usize;
         let current_decision_level = self.current_decision_level;
@@ -276,29 +319,100 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
                 // or if we backtracked in the meantime.
                 check_all || pa.highest_decision_level == current_decision_level
             })
-            .filter_map(|(p, pa)| pa.assignments_intersection.potential_package_filter(p))
+            .filter_map(|(&p, pa)| pa.assignments_intersection.potential_package_filter(p))
             .for_each(|(p, r)| {
                 let priority = prioritizer(p, r);
-                prioritized_potential_packages.push(p.clone(), priority);
+                prioritized_potential_packages.push(p, priority);
             });
+        for package in self.outdated_priorities.drain(..) {
+            let Some((p, r)) = self.package_assignments.get(&package).and_then(|pa| {
+                pa.assignments_intersection
+                    .potential_package_filter(package)
+            }) else {
+                continue;
+            };
+            prioritized_potential_packages.push(p, prioritizer(p, r));
+        }
         self.changed_this_decision_level = self.package_assignments.len();
         prioritized_potential_packages.pop().map(|(p, _)| p)
     }
 
+    /// Compute the priority of a package again the next time the package with the highest priority
+    /// is picked, because what the prioritizer knows about it changed.
+    pub(crate) fn outdate_priority(&mut self, package: Id<DP::P>) {
+        self.outdated_priorities.push(package);
+    }
+
     /// If a partial solution has, for every positive derivation,
     /// a corresponding decision that satisfies that assignment,
     /// it's a total solution and version solving has succeeded.
-    pub(crate) fn extract_solution(&self) -> SelectedDependencies<DP> {
+    pub(crate) fn extract_solution(
+        &self,
+        package_store: &HashArena<DP::P>,
+    ) -> SelectedDependencies<DP> {
+        self.decisions()
+            .map(|(p, v)| (package_store[p].clone(), v.clone()))
+            .collect()
+    }
+
+    /// Iterate over the decisions, in the order they were made.
+    pub(crate) fn decisions(&self) -> impl Iterator<Item = (Id<DP::P>, &DP::V)> {
         self.package_assignments
             .iter()
             .take(self.current_decision_level.0 as usize)
-            .map(|(p, pa)| match &pa.assignments_intersection {
-                AssignmentsIntersection::Decision((_, v, _)) => (p.clone(), v.clone()),
+            .map(|(&p, pa)| match &pa.assignments_intersection {
+                AssignmentsIntersection::Decision((_, v, _)) => (p, v),
                 AssignmentsIntersection::Derivations(_) => {
                     panic!("Derivations in the Decision part")
                 }
             })
-            .collect()
+    }
+
+    /// The decision made for a package, if any.
+    pub(crate) fn decision(&self, package: Id<DP::P>) -> Option<&DP::V> {
+        match &self
+            .package_assignments
+            .get(&package)?
+            .assignments_intersection
+        {
+            AssignmentsIntersection::Decision((_, v, _)) => Some(v),
+            AssignmentsIntersection::Derivations(_) => None,
+        }
+    }
+
+    /// The assignments of a package with their global index, in the order they were made.
+    pub(crate) fn assignments(
+        &self,
+        package: Id<DP::P>,
+    ) -> impl Iterator<Item = (u32, Assignment<'_, DP>)> {
+        let pa = self.package_assignments.get(&package);
+        let derivations = pa
+            .into_iter()
+            .flat_map(|pa| pa.dated_derivations.iter())
+            .map(|dd| (dd.global_index, Assignment::Derivation(dd.cause)));
+        let decision = pa.and_then(|pa| match &pa.assignments_intersection {
+            AssignmentsIntersection::Decision((global_index, v, _)) => {
+                Some((*global_index, Assignment::Decision(v)))
+            }
+            AssignmentsIntersection::Derivations(_) => None,
+        });
+        derivations.chain(decision)
+    }
+
+    /// The global index and the cause of the earliest derivation excluding a version of a package,
+    /// if any.
+    pub(crate) fn excluded_by(
+        &self,
+        package: Id<DP::P>,
+        version: &DP::V,
+    ) -> Option<(u32, IncompDpId<DP>)> {
+        let term = Term::exact(version.clone());
+        self.package_assignments
+            .get(&package)?
+            .dated_derivations
+            .iter()
+            .find(|dd| dd.accumulated_intersection.is_disjoint(&term))
+            .map(|dd| (dd.global_index, dd.cause))
     }
 
     /// Backtrack the partial solution to a given decision level.
@@ -339,6 +453,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
         });
         // Throw away all stored priority levels, And mark that they all need to be recomputed.
         self.prioritized_potential_packages.clear();
+        self.outdated_priorities.clear();
         self.changed_this_decision_level = self.current_decision_level.0.saturating_sub(1) as usize;
         self.has_ever_backtracked = true;
     }
@@ -348,44 +463,54 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
     /// In practice I think it can only produce a conflict if one of the dependencies
     /// (which are used to make the new incompatibilities)
     /// is already in the partial solution with an incompatible version.
+    ///
+    /// Returns the new incompatibility that conflicts, if any, in which case no decision is made.
     pub(crate) fn add_version(
         &mut self,
-        package: DP::P,
+        package: Id<DP::P>,
         version: DP::V,
         new_incompatibilities: std::ops::Range<IncompId<DP::P, DP::VS, DP::M>>,
         store: &Arena<Incompatibility<DP::P, DP::VS, DP::M>>,
-    ) {
+        package_store: &HashArena<DP::P>,
+    ) -> Option<IncompId<DP::P, DP::VS, DP::M>> {
         if !self.has_ever_backtracked {
             // Nothing has yet gone wrong during this resolution. This call is unlikely to be the first problem.
             // So let's live with a little bit of risk and add the decision without checking the dependencies.
             // The worst that can happen is we will have to do a full backtrack which only removes this one decision.
-            log::info!("add_decision: {package} @ {version} without checking dependencies");
+            log::info!(
+                "add_decision: {} @ {version} without checking dependencies",
+                package_store[package]
+            );
             self.add_decision(package, version);
+            None
         } else {
             // Check if any of the new dependencies preclude deciding on this crate version.
             let exact = Term::exact(version.clone());
-            let not_satisfied = |incompat: &Incompatibility<DP::P, DP::VS, DP::M>| {
+            let satisfied = |incompat: &Incompatibility<DP::P, DP::VS, DP::M>| {
                 incompat.relation(|p| {
-                    if p == &package {
+                    if p == package {
                         Some(&exact)
                     } else {
                         self.term_intersection_for_package(p)
                     }
-                }) != Relation::Satisfied
+                }) == Relation::Satisfied
             };
 
             // Check none of the dependencies (new_incompatibilities)
             // would create a conflict (be satisfied).
-            if store[new_incompatibilities].iter().all(not_satisfied) {
-                log::info!("add_decision: {} @ {}", package, version);
+            let conflict =
+                Id::range_to_iter(new_incompatibilities).find(|&id| satisfied(&store[id]));
+            if conflict.is_none() {
+                log::info!("add_decision: {} @ {}", package_store[package], version);
                 self.add_decision(package, version);
             } else {
                 log::info!(
                     "not adding {} @ {} because of its dependencies",
-                    package,
+                    package_store[package],
                     version
                 );
             }
+            conflict
         }
     }
 
@@ -398,19 +523,42 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
     }
 
     /// Retrieve intersection of terms related to package.
-    pub(crate) fn term_intersection_for_package(&self, package: &DP::P) -> Option<&Term<DP::VS>> {
+    pub(crate) fn term_intersection_for_package(
+        &self,
+        package: Id<DP::P>,
+    ) -> Option<&Term<DP::VS>> {
         self.package_assignments
-            .get(package)
+            .get(&package)
             .map(|pa| pa.assignments_intersection.term())
     }
 
+    /// The decision level at which the partial solution started satisfying
+    /// the term of a package in an incompatibility, or [None] if it does not satisfy it.
+    pub(crate) fn satisfier_level(
+        &self,
+        package: Id<DP::P>,
+        incompat_term: &Term<DP::VS>,
+    ) -> Option<DecisionLevel> {
+        let pa = self.package_assignments.get(&package)?;
+        if !matches!(
+            incompat_term.relation_with(pa.assignments_intersection.term()),
+            term::Relation::Satisfied
+        ) {
+            return None;
+        }
+        Some(pa.satisfier(package, &incompat_term.negate()).2)
+    }
+
     /// Figure out if the satisfier and previous satisfier are of different decision levels.
+    ///
+    /// The previous satisfier level is never lower than `lowest_level`.
     #[allow(clippy::type_complexity)]
-    pub(crate) fn satisfier_search<'i>(
+    pub(crate) fn satisfier_search(
         &self,
-        incompat: &'i Incompatibility<DP::P, DP::VS, DP::M>,
+        incompat: &Incompatibility<DP::P, DP::VS, DP::M>,
         store: &Arena<Incompatibility<DP::P, DP::VS, DP::M>>,
-    ) -> (&'i DP::P, SatisfierSearch<DP::P, DP::VS, DP::M>) {
+        lowest_level: DecisionLevel,
+    ) -> (Id<DP::P>, SatisfierSearch<DP::P, DP::VS, DP::M>) {
         let satisfied_map = Self::find_satisfier(incompat, &self.package_assignments);
         let (&satisfier_package, &(satisfier_cause, _, satisfier_decision_level)) = satisfied_map
             .iter()
@@ -422,6 +570,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
             satisfied_map,
             &self.package_assignments,
             store,
+            lowest_level,
         );
         let search_result = if previous_satisfier_level >= satisfier_decision_level {
             SatisfierSearch::SameDecisionLevels {
@@ -445,13 +594,13 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
     /// It would be nice if we could get rid of it, but I don't know if then it will be possible
     /// to return a coherent previous_satisfier_level.
     #[allow(clippy::type_complexity)]
-    fn find_satisfier<'i>(
-        incompat: &'i Incompatibility<DP::P, DP::VS, DP::M>,
-        package_assignments: &FnvIndexMap<DP::P, PackageAssignments<DP::P, DP::VS, DP::M>>,
-    ) -> SatisfiedMap<'i, DP::P, DP::VS, DP::M> {
+    fn find_satisfier(
+        incompat: &Incompatibility<DP::P, DP::VS, DP::M>,
+        package_assignments: &FnvIndexMap<Id<DP::P>, PackageAssignments<DP::P, DP::VS, DP::M>>,
+    ) -> SatisfiedMap<DP::P, DP::VS, DP::M> {
         let mut satisfied = SmallMap::Empty;
         for (package, incompat_term) in incompat.iter() {
-            let pa = package_assignments.get(package).expect("Must exist");
+            let pa = package_assignments.get(&package).expect("Must exist");
             satisfied.insert(package, pa.satisfier(package, &incompat_term.negate()));
         }
         satisfied
@@ -461,15 +610,16 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
     /// such that incompatibility is satisfied by the partial solution up to
     /// and including that assignment plus satisfier.
     #[allow(clippy::type_complexity)]
-    fn find_previous_satisfier<'i>(
+    fn find_previous_satisfier(
         incompat: &Incompatibility<DP::P, DP::VS, DP::M>,
-        satisfier_package: &'i DP::P,
-        mut satisfied_map: SatisfiedMap<'i, DP::P, DP::VS, DP::M>,
-        package_assignments: &FnvIndexMap<DP::P, PackageAssignments<DP::P, DP::VS, DP::M>>,
+        satisfier_package: Id<DP::P>,
+        mut satisfied_map: SatisfiedMap<DP::P, DP::VS, DP::M>,
+        package_assignments: &FnvIndexMap<Id<DP::P>, PackageAssignments<DP::P, DP::VS, DP::M>>,
         store: &Arena<Incompatibility<DP::P, DP::VS, DP::M>>,
+        lowest_level: DecisionLevel,
     ) -> DecisionLevel {
         // First, let's retrieve the previous derivations and the initial accum_term.
-        let satisfier_pa = package_assignments.get(satisfier_package).unwrap();
+        let satisfier_pa = package_assignments.get(&satisfier_package).unwrap();
         let (satisfier_cause, _gidx, _dl) = satisfied_map.get(&satisfier_package).unwrap();
 
         let accum_term = if let &Some(cause) = satisfier_cause {
@@ -498,7 +648,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
             .iter()
             .max_by_key(|(_p, (_, global_index, _))| global_index)
             .unwrap();
-        decision_level.max(DecisionLevel(1))
+        decision_level.max(lowest_level)
     }
 
     pub(crate) fn current_decision_level(&self) -> DecisionLevel {
@@ -509,7 +659,7 @@ impl<DP: DependencyProvider> PartialSolution<DP> {
 impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> PackageAssignments<P, VS, M> {
     fn satisfier(
         &self,
-        package: &P,
+        package: Id<P>,
         start_term: &Term<VS>,
     ) -> (Option<IncompId<P, VS, M>>, u32, DecisionLevel) {
         let empty = Term::empty();
@@ -522,6 +672,11 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> PackageAssignm
             debug_assert_eq!(dd.accumulated_intersection.intersection(start_term), empty);
             return (Some(dd.cause), dd.global_index, dd.decision_level);
         }
+        // A package decided without any derivation, like an alternative of a dependency
+        // on any of several packages, satisfies an empty term before any assignment.
+        if start_term == &empty {
+            return (None, 0, DecisionLevel(0));
+        }
         // If it wasn't found in the derivations,
         // it must be the decision which is last (if called in the right context).
         match &self.assignments_intersection {
@@ -531,7 +686,7 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> PackageAssignm
             AssignmentsIntersection::Derivations(accumulated_intersection) => {
                 unreachable!(
                     concat!(
-                        "while processing package {}: ",
+                        "while processing package {:?}: ",
                         "accum_term = {} has overlap with incompat_term = {}, ",
                         "which means the last assignment should have been a decision, ",
                         "but instead it was a derivation. This shouldn't be possible! ",
@@ -557,10 +712,7 @@ impl<VS: VersionSet> AssignmentsIntersection<VS> {
     /// selected version (no "decision")
     /// and if it contains at least one positive derivation term
     /// in the partial solution.
-    fn potential_package_filter<'a, P: Package>(
-        &'a self,
-        package: &'a P,
-    ) -> Option<(&'a P, &'a VS)> {
+    fn potential_package_filter<P: Package>(&self, package: Id<P>) -> Option<(Id<P>, &VS)> {
         match self {
             Self::Decision(_) => None,
             Self::Derivations(term_intersection) => {
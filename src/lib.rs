@@ -71,7 +71,10 @@
 //! and [SemanticVersion] for versions.
 //! This may be done quite easily by implementing the three following functions.
 //! ```
-//! # use pubgrub::{DependencyProvider, Dependencies, SemanticVersion, Ranges, DependencyConstraints, Map};
+//! # use pubgrub::{
+//! #     DependencyProvider, Dependencies, SemanticVersion, Ranges, DependencyConstraints, Map,
+//! #     PackageResolutionStatistics,
+//! # };
 //! # use std::error::Error;
 //! # use std::borrow::Borrow;
 //! # use std::convert::Infallible;
@@ -86,7 +89,12 @@
 //!     }
 //!
 //!     type Priority = usize;
-//!     fn prioritize(&self, package: &String, range: &SemVS) -> Self::Priority {
+//!     fn prioritize(
+//!         &self,
+//!         package: &String,
+//!         range: &SemVS,
+//!         package_statistics: &PackageResolutionStatistics,
+//!     ) -> Self::Priority {
 //!         unimplemented!()
 //!     }
 //!
@@ -210,7 +218,9 @@
 
 #![warn(missing_docs)]
 
+mod bucket;
 mod error;
+mod observer;
 mod package;
 mod report;
 mod solver;
@@ -219,13 +229,23 @@ mod type_aliases;
 mod version;
 mod version_set;
 
-pub use error::{NoSolutionError, PubGrubError};
+pub use bucket::{Bucketed, BucketedPackage, Buckets};
+pub use error::{NoSolutionError, PartialResolution, PubGrubError};
+pub use observer::{IncompatibilityTerms, Observer};
 pub use package::Package;
 pub use report::{
     DefaultStringReportFormatter, DefaultStringReporter, DerivationTree, Derived, External,
-    ReportFormatter, Reporter,
+    ReportFormatter, Reporter, SelectionFormatter, SelectionReason, SelectionStep,
+};
+pub use solver::{
+    resolve, resolve_all, resolve_async, resolve_optimal, resolve_requirements, resolve_universal,
+    resolve_upgrade, resolve_with_graph, resolve_with_observer, resolve_with_options,
+    resolve_with_stats, AsyncDependencyProvider, BlockedUpgrade, Dependencies, Dependency,
+    DependencyEdge, DependencyProvider, EnvironmentDependencyProvider, LearnedIncompatibilities,
+    Limit, LockChange, NewerVersion, OfflineDependencyProvider, Optimum,
+    PackageResolutionStatistics, ProviderTypes, ResolutionGraph, ResolutionStats, ResolveOptions,
+    RestartPolicy, Solutions, Solver, UniversalSolution, Upgrade,
 };
-pub use solver::{resolve, Dependencies, DependencyProvider, OfflineDependencyProvider};
 pub use term::Term;
 pub use type_aliases::{DependencyConstraints, Map, SelectedDependencies, Set};
 pub use version::{SemanticVersion, VersionParseError};
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Observe the steps of the resolution as they happen.
+
+use std::fmt::{self, Debug};
+
+use crate::internal::{HashArena, Id, SmallMap};
+use crate::{Package, Term, VersionSet};
+
+/// Callbacks for the events of a resolution,
+/// to trace it, display its progress or debug it.
+///
+/// All methods do nothing by default, so an implementor only overrides what it needs.
+/// The unit type `()` is the observer that ignores every event.
+pub trait Observer<P: Package, VS: VersionSet> {
+    /// A version of a package was decided on.
+    fn decision(&self, _package: &P, _version: &VS::V) {}
+
+    /// Unit propagation derived a new term about a package.
+    ///
+    /// The given term is the intersection of everything known about that package so far,
+    /// including this derivation.
+    fn derivation(&self, _package: &P, _term: &Term<VS>) {}
+
+    /// An incompatibility is satisfied by the partial solution,
+    /// which starts conflict resolution.
+    fn conflict(&self, _incompatibility: IncompatibilityTerms<'_, P, VS>) {}
+
+    /// Conflict resolution learned a new incompatibility.
+    fn learned(&self, _incompatibility: IncompatibilityTerms<'_, P, VS>) {}
+
+    /// The partial solution was backtracked to the given decision level.
+    fn backtrack(&self, _decision_level: u32) {}
+}
+
+impl<P: Package, VS: VersionSet> Observer<P, VS> for () {}
+
+/// The terms of an incompatibility reported to an [Observer].
+///
+/// Packages are only looked up while iterating over the terms,
+/// so the events an observer ignores cost nothing.
+pub struct IncompatibilityTerms<'a, P: Package, VS: VersionSet> {
+    terms: &'a SmallMap<Id<P>, Term<VS>>,
+    package_store: &'a HashArena<P>,
+}
+
+impl<'a, P: Package, VS: VersionSet> IncompatibilityTerms<'a, P, VS> {
+    pub(crate) fn new(
+        terms: &'a SmallMap<Id<P>, Term<VS>>,
+        package_store: &'a HashArena<P>,
+    ) -> Self {
+        Self {
+            terms,
+            package_store,
+        }
+    }
+
+    /// Number of terms of the incompatibility.
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// Whether the incompatibility has no terms, meaning that there is no solution.
+    pub fn is_empty(&self) -> bool {
+        self.terms.len() == 0
+    }
+
+    /// Iterate over the terms of the incompatibility, by package.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a P, &'a Term<VS>)> + 'a {
+        let package_store = self.package_store;
+        self.terms
+            .iter()
+            .map(move |(package, term)| (&package_store[*package], term))
+    }
+}
+
+// Implemented by hand so that the packages and version sets do not need to be copyable.
+impl<P: Package, VS: VersionSet> Clone for IncompatibilityTerms<'_, P, VS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: Package, VS: VersionSet> Copy for IncompatibilityTerms<'_, P, VS> {}
+
+impl<P: Package, VS: VersionSet> Debug for IncompatibilityTerms<'_, P, VS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -38,16 +38,39 @@ pub enum DerivationTree<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Disp
 
 /// Incompatibilities that are not derived from others,
 /// they have their own reason.
+///
+/// New kinds of external incompatibilities may be added in future versions,
+/// so a custom [ReportFormatter] should keep a fallback for the others,
+/// like their [Display] implementation.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum External<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
     /// Initial incompatibility aiming at picking the root package for the first decision.
     NotRoot(P, VS::V),
+    /// The requirements we are solving need a version of this package in the given set.
+    Requirement(P, VS),
+    /// The requirements we are solving forbid this package in the given set.
+    Exclusion(P, VS),
+    /// The package is locked to this version, if it is selected.
+    Locked(P, VS::V),
     /// There are no versions in the given set for this package.
     NoVersions(P, VS),
     /// Incompatibility coming from the dependencies of a given package.
     FromDependencyOf(P, VS, P, VS),
+    /// Incompatibility coming from a constraint of a given package on another one:
+    /// if the other package is selected, it must be in the given set.
+    FromConstraintOf(P, VS, P, VS),
+    /// Incompatibility coming from a dependency of a given package
+    /// on any of several packages, none of which can be selected.
+    FromAnyDependencyOf(P, VS, Vec<(P, VS)>),
+    /// A package provides a version of a virtual package,
+    /// which must then have that version.
+    Provides(P, VS, P, VS::V),
     /// The package is unusable for reasons outside pubgrub.
     Custom(P, VS, M),
+    /// The search does not need to explore this combination of versions anymore,
+    /// for example because it is a solution that was already found.
+    Pruned(Map<P, VS>),
 }
 
 /// Incompatibility derived from two others.
@@ -73,15 +96,27 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> DerivationTree
         let mut packages = Set::default();
         match self {
             Self::External(external) => match external {
-                External::FromDependencyOf(p, _, p2, _) => {
+                External::FromDependencyOf(p, _, p2, _)
+                | External::FromConstraintOf(p, _, p2, _)
+                | External::Provides(p, _, p2, _) => {
                     packages.insert(p);
                     packages.insert(p2);
                 }
+                External::FromAnyDependencyOf(p, _, alternatives) => {
+                    packages.insert(p);
+                    packages.extend(alternatives.iter().map(|(p2, _)| p2));
+                }
                 External::NoVersions(p, _)
                 | External::NotRoot(p, _)
+                | External::Requirement(p, _)
+                | External::Exclusion(p, _)
+                | External::Locked(p, _)
                 | External::Custom(p, _, _) => {
                     packages.insert(p);
                 }
+                External::Pruned(versions) => {
+                    packages.extend(versions.keys());
+                }
             },
             Self::Derived(derived) => {
                 // Less efficient than recursing with a `&mut Set<&P>`, but it's sufficient for
@@ -144,6 +179,10 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> DerivationTree
             //
             // Cannot be merged because the reason may not match
             DerivationTree::External(External::NoVersions(_, _)) => None,
+            // Cannot be merged because the requirements are not about available versions
+            DerivationTree::External(
+                External::Requirement(_, _) | External::Exclusion(_, _) | External::Locked(_, _),
+            ) => None,
             DerivationTree::External(External::FromDependencyOf(p1, r1, p2, r2)) => {
                 if p1 == package {
                     Some(DerivationTree::External(External::FromDependencyOf(
@@ -161,8 +200,48 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> DerivationTree
                     )))
                 }
             }
+            DerivationTree::External(External::FromConstraintOf(p1, r1, p2, r2)) => {
+                if p1 == package {
+                    Some(DerivationTree::External(External::FromConstraintOf(
+                        p1,
+                        r1.union(&set),
+                        p2,
+                        r2,
+                    )))
+                } else {
+                    // The constrained package is not required, so it cannot be merged
+                    None
+                }
+            }
+            DerivationTree::External(External::FromAnyDependencyOf(p1, r1, alternatives)) => {
+                if p1 == package {
+                    Some(DerivationTree::External(External::FromAnyDependencyOf(
+                        p1,
+                        r1.union(&set),
+                        alternatives,
+                    )))
+                } else {
+                    // Only one of the alternatives has no versions, the others may have some
+                    None
+                }
+            }
+            DerivationTree::External(External::Provides(p1, r1, p2, v2)) => {
+                if p1 == package {
+                    Some(DerivationTree::External(External::Provides(
+                        p1,
+                        r1.union(&set),
+                        p2,
+                        v2,
+                    )))
+                } else {
+                    // The virtual package has a single version, it cannot be merged
+                    None
+                }
+            }
             // Cannot be merged because the reason may not match
             DerivationTree::External(External::Custom(_, _, _)) => None,
+            // Cannot be merged because it is not about available versions
+            DerivationTree::External(External::Pruned(_)) => None,
         }
     }
 }
@@ -173,6 +252,23 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Display for Ex
             Self::NotRoot(package, version) => {
                 write!(f, "we are solving dependencies of {} {}", package, version)
             }
+            Self::Requirement(package, set) => {
+                if set == &VS::full() {
+                    write!(f, "your requirements include {}", package)
+                } else {
+                    write!(f, "your requirements include {} {}", package, set)
+                }
+            }
+            Self::Exclusion(package, set) => {
+                if set == &VS::full() {
+                    write!(f, "your requirements exclude {}", package)
+                } else {
+                    write!(f, "your requirements exclude {} {}", package, set)
+                }
+            }
+            Self::Locked(package, version) => {
+                write!(f, "{} is locked to {}", package, version)
+            }
             Self::NoVersions(package, set) => {
                 if set == &VS::full() {
                     write!(f, "there is no available version for {}", package)
@@ -206,6 +302,55 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> Display for Ex
                     write!(f, "{} {} depends on {} {}", p, set_p, dep, set_dep)
                 }
             }
+            Self::FromConstraintOf(p, set_p, dep, set_dep) => {
+                let conflicting = set_dep.complement();
+                if set_p == &VS::full() && conflicting == VS::full() {
+                    write!(f, "{} conflicts with {}", p, dep)
+                } else if set_p == &VS::full() {
+                    write!(f, "{} conflicts with {} {}", p, dep, conflicting)
+                } else if conflicting == VS::full() {
+                    write!(f, "{} {} conflicts with {}", p, set_p, dep)
+                } else {
+                    write!(f, "{} {} conflicts with {} {}", p, set_p, dep, conflicting)
+                }
+            }
+            Self::FromAnyDependencyOf(p, set_p, alternatives) => {
+                let alternatives: Vec<_> = alternatives
+                    .iter()
+                    .map(|(dep, set_dep)| {
+                        if set_dep == &VS::full() {
+                            dep.to_string()
+                        } else {
+                            format!("{} {}", dep, set_dep)
+                        }
+                    })
+                    .collect();
+                let alternatives = if alternatives.is_empty() {
+                    "one of no packages".to_string()
+                } else {
+                    alternatives.join(" or ")
+                };
+                if set_p == &VS::full() {
+                    write!(f, "{} depends on {}", p, alternatives)
+                } else {
+                    write!(f, "{} {} depends on {}", p, set_p, alternatives)
+                }
+            }
+            Self::Provides(p, set_p, virtual_package, version) => {
+                if set_p == &VS::full() {
+                    write!(f, "{} provides {} {}", p, virtual_package, version)
+                } else {
+                    write!(
+                        f,
+                        "{} {} provides {} {}",
+                        p, set_p, virtual_package, version
+                    )
+                }
+            }
+            Self::Pruned(versions) => {
+                let versions: Vec<_> = versions.iter().map(|(p, v)| format!("{p} {v}")).collect();
+                write!(f, "{} was already explored", versions.join(", "))
+            }
         }
     }
 }
@@ -274,6 +419,82 @@ pub trait ReportFormatter<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Di
     ) -> Self::Output;
 }
 
+/// Why a package version is part of a solution, from [Solver::why_selected](crate::Solver::why_selected).
+#[derive(Debug, Clone)]
+pub struct SelectionReason<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The decisions and derivations that led to the selection, in the order they were made.
+    /// The last one is the decision on the selected version.
+    pub steps: Vec<SelectionStep<P, VS, M>>,
+}
+
+/// A step of a [SelectionReason].
+#[derive(Debug, Clone)]
+pub enum SelectionStep<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// A version of a package was decided on.
+    Decision {
+        /// The decided package.
+        package: P,
+        /// The decided version.
+        version: VS::V,
+    },
+    /// A term about a package was derived from an incompatibility,
+    /// introducing the package or narrowing its range.
+    Derivation {
+        /// The package the term is about.
+        package: P,
+        /// The derived term.
+        term: Term<VS>,
+        /// The incompatibility the term was derived from.
+        cause: DerivationTree<P, VS, M>,
+    },
+}
+
+impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> SelectionReason<P, VS, M> {
+    /// Explain the selection as one line per step, using the default formatter.
+    pub fn report(&self) -> String {
+        self.report_with_formatter(&DefaultStringReportFormatter)
+            .join("\n")
+    }
+
+    /// Explain the selection as one output per step, using a custom formatter.
+    pub fn report_with_formatter<F: SelectionFormatter<P, VS, M>>(
+        &self,
+        formatter: &F,
+    ) -> Vec<F::Output> {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                SelectionStep::Decision { package, version } => {
+                    formatter.format_decision(package, version)
+                }
+                SelectionStep::Derivation {
+                    package,
+                    term,
+                    cause,
+                } => formatter.format_derivation(package, term, cause),
+            })
+            .collect()
+    }
+}
+
+/// Trait for formatting the steps of a [SelectionReason],
+/// the counterpart of [ReportFormatter] for successful resolutions.
+pub trait SelectionFormatter<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// Output type of each step.
+    type Output;
+
+    /// Format a decision on a version of a package.
+    fn format_decision(&self, package: &P, version: &VS::V) -> Self::Output;
+
+    /// Format a term derived from an incompatibility.
+    fn format_derivation(
+        &self,
+        package: &P,
+        term: &Term<VS>,
+        cause: &DerivationTree<P, VS, M>,
+    ) -> Self::Output;
+}
+
 /// Default formatter for the default reporter.
 #[derive(Default, Debug)]
 pub struct DefaultStringReportFormatter;
@@ -407,6 +628,45 @@ impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> ReportFormatte
     }
 }
 
+impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> SelectionFormatter<P, VS, M>
+    for DefaultStringReportFormatter
+{
+    type Output = String;
+
+    fn format_decision(&self, package: &P, version: &VS::V) -> String {
+        format!("{} {} is selected", package, version)
+    }
+
+    fn format_derivation(
+        &self,
+        package: &P,
+        term: &Term<VS>,
+        cause: &DerivationTree<P, VS, M>,
+    ) -> String {
+        let conclusion = match term {
+            Term::Positive(range) => format!("{} {} is required", package, range),
+            Term::Negative(range) => format!("{} {} is forbidden", package, range),
+        };
+        match cause {
+            DerivationTree::External(external) => format!(
+                "Because {}, {}",
+                ReportFormatter::<P, VS, M>::format_external(self, external),
+                conclusion
+            ),
+            // The explanation of a derived incompatibility about that package alone
+            // already concludes with the derived term.
+            DerivationTree::Derived(derived) if derived.terms.len() == 1 => {
+                DefaultStringReporter::report_with_formatter(cause, self)
+            }
+            DerivationTree::Derived(_) => format!(
+                "{}\nSo, {}",
+                DefaultStringReporter::report_with_formatter(cause, self),
+                conclusion
+            ),
+        }
+    }
+}
+
 /// Default reporter able to generate an explanation as a [String].
 pub struct DefaultStringReporter {
     /// Number of explanations already with a line reference.
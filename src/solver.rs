@@ -64,11 +64,16 @@ use std::collections::{BTreeMap, BTreeSet as Set};
 use std::convert::Infallible;
 use std::error::Error;
 use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::time::{Duration, Instant};
 
 use log::{debug, info};
 
-use crate::internal::{Incompatibility, State};
-use crate::{DependencyConstraints, Map, Package, PubGrubError, SelectedDependencies, VersionSet};
+use crate::internal::{Arena, HashArena, Id, IncompId, Incompatibility, SmallVec, State};
+use crate::{
+    DependencyConstraints, DerivationTree, Map, NoSolutionError, Observer, Package,
+    PartialResolution, PubGrubError, SelectedDependencies, SelectionReason, Term, VersionSet,
+};
 
 /// Main function of the library.
 /// Finds a set of packages satisfying dependency bounds for a given package + version pair.
@@ -77,112 +82,1926 @@ pub fn resolve<DP: DependencyProvider>(
     package: DP::P,
     version: impl Into<DP::V>,
 ) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
-    let mut state: State<DP> = State::init(package.clone(), version.into());
-    let mut added_dependencies: Map<DP::P, Set<DP::V>> = Map::default();
-    let mut next = package;
-    loop {
-        dependency_provider
-            .should_cancel()
-            .map_err(PubGrubError::ErrorInShouldCancel)?;
+    Solver::new(package, version).solve(dependency_provider)
+}
 
-        info!("unit_propagation: {}", &next);
-        state.unit_propagation(next)?;
+/// Finds a set of packages satisfying a set of requirements,
+/// without introducing a root package.
+///
+/// Every package in `requirements` must be selected at a version in the given set,
+/// while packages in `exclusions` may only be selected outside of the given set.
+/// When there is no solution, the derivation tree explains it in terms of
+/// [Requirement](crate::External::Requirement) and [Exclusion](crate::External::Exclusion)
+/// instead of the dependencies of a root package.
+pub fn resolve_requirements<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    requirements: DependencyConstraints<DP::P, DP::VS>,
+    exclusions: DependencyConstraints<DP::P, DP::VS>,
+) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+    Solver::from_requirements(requirements, exclusions).solve(dependency_provider)
+}
 
-        debug!(
-            "Partial solution after unit propagation: {}",
-            state.partial_solution
-        );
+/// Re-resolve the dependencies of a package + version pair after a previous solution,
+/// keeping every package at its `locked` version except the ones in `unlock`.
+///
+/// With `unlock_dependencies`, the locked dependencies of the unlocked packages
+/// are unlocked too, transitively.
+/// Each package of `unlock` that could not move to the version the dependency provider prefers
+/// because of locked packages is listed in [Upgrade::blocked], naming those packages.
+#[allow(clippy::type_complexity)]
+pub fn resolve_upgrade<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+    locked: &SelectedDependencies<DP>,
+    unlock: impl IntoIterator<Item = DP::P>,
+    unlock_dependencies: bool,
+) -> Result<Upgrade<DP::P, DP::VS, DP::M>, PubGrubError<DP>> {
+    let requested: Vec<DP::P> = unlock.into_iter().collect();
+    let mut unlocked: crate::Set<DP::P> = requested.iter().cloned().collect();
+    if unlock_dependencies {
+        let mut stack = requested.clone();
+        while let Some(package) = stack.pop() {
+            let Some(version) = locked.get(&package) else {
+                continue;
+            };
+            let dependencies = dependency_provider
+                .get_dependencies(&package, version)
+                .map_err(|err| PubGrubError::ErrorRetrievingDependencies {
+                    package: package.clone(),
+                    version: version.clone(),
+                    source: err,
+                })?;
+            let dependencies: Vec<DP::P> = match dependencies {
+                Dependencies::Unavailable(_) => continue,
+                Dependencies::Available(dependencies) => dependencies.into_keys().collect(),
+                Dependencies::Extended(dependencies) => dependencies
+                    .into_iter()
+                    .flat_map(|dependency| match dependency {
+                        Dependency::Requires(p, _) => vec![p],
+                        Dependency::Constrains(_, _) => Vec::new(),
+                        Dependency::AnyOf(alternatives) => {
+                            alternatives.into_iter().map(|(p, _)| p).collect()
+                        }
+                        Dependency::Provides(_, _) => Vec::new(),
+                    })
+                    .collect(),
+            };
+            for dependency in dependencies {
+                if unlocked.insert(dependency.clone()) {
+                    stack.push(dependency);
+                }
+            }
+        }
+    }
 
-        let Some(highest_priority_pkg) = state
-            .partial_solution
-            .pick_highest_priority_pkg(|p, r| dependency_provider.prioritize(p, r))
+    let mut solver = Solver::new(package, version).with_locked(locked, &unlocked);
+    let solution = solver.solve(dependency_provider)?;
+    let mut blocked = Vec::new();
+    for package in requested {
+        let Some(selected) = solution.get(&package) else {
+            continue;
+        };
+        let Some(preferred) = dependency_provider
+            .choose_version(&package, &DP::VS::full())
+            .map_err(PubGrubError::ErrorChoosingPackageVersion)?
         else {
-            return Ok(state.partial_solution.extract_solution());
+            continue;
         };
-        next = highest_priority_pkg;
+        if &preferred <= selected {
+            continue;
+        }
+        let locked = solver.state.locks_excluding(&package, &preferred);
+        if locked.is_empty() {
+            continue;
+        }
+        if let Some(reason) = solver.state.explain_exclusion(&package, &preferred) {
+            blocked.push(BlockedUpgrade {
+                package,
+                version: preferred,
+                locked,
+                reason,
+            });
+        }
+    }
+    Ok(Upgrade { solution, blocked })
+}
 
-        let term_intersection = state
-            .partial_solution
-            .term_intersection_for_package(&next)
-            .ok_or_else(|| {
-                PubGrubError::Failure("a package was chosen but we don't have a term.".into())
-            })?;
-        let decision = dependency_provider
-            .choose_version(&next, term_intersection.unwrap_positive())
-            .map_err(PubGrubError::ErrorChoosingPackageVersion)?;
-        info!("DP chose: {} @ {:?}", next, decision);
+/// Finds the dependencies of a given package + version pair for several environments at once,
+/// with one solution for each group of environments that resolve the same way.
+///
+/// See [Solver::solve_universal].
+#[allow(clippy::type_complexity)]
+pub fn resolve_universal<DP: EnvironmentDependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+    environments: impl IntoIterator<Item = DP::Environment>,
+) -> Result<UniversalSolution<DP::P, DP::V, DP::Environment>, PubGrubError<DP>> {
+    Solver::new(package, version).solve_universal(dependency_provider, environments)
+}
+
+/// Finds a set of packages satisfying dependency bounds for a given package + version pair,
+/// awaiting the [AsyncDependencyProvider] instead of blocking on it.
+///
+/// The resolution itself is the same as [resolve],
+/// so many resolutions can run concurrently on a single executor.
+pub async fn resolve_async<DP: AsyncDependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+    Solver::new(package, version)
+        .solve_async(dependency_provider)
+        .await
+}
+
+/// Same as [resolve], reporting the steps of the resolution to an [Observer].
+pub fn resolve_with_observer<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+    observer: &impl Observer<DP::P, DP::VS>,
+) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+    Solver::new(package, version).solve_with_observer(dependency_provider, observer)
+}
+
+/// Same as [resolve], also returning statistics about the resolution,
+/// whether it succeeded or not.
+#[allow(clippy::type_complexity)]
+pub fn resolve_with_stats<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+) -> (
+    Result<SelectedDependencies<DP>, PubGrubError<DP>>,
+    ResolutionStats,
+) {
+    let options = ResolveOptions {
+        measure_time: true,
+        ..ResolveOptions::default()
+    };
+    let mut solver = Solver::new(package, version).with_options(options);
+    let result = solver.solve(dependency_provider);
+    (result, solver.stats().clone())
+}
+
+/// Same as [resolve], also returning the dependencies between the packages of the solution.
+pub fn resolve_with_graph<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+) -> Result<ResolutionGraph<DP::P, DP::VS>, PubGrubError<DP>> {
+    let mut solver = Solver::new(package, version);
+    let solution = solver.solve(dependency_provider)?;
+    Ok(ResolutionGraph {
+        solution,
+        edges: solver.dependency_edges(),
+    })
+}
+
+/// Same as [resolve], stopping the resolution when it reaches one of the limits of `options`.
+///
+/// Reaching a limit fails with [PubGrubError::LimitReached].
+pub fn resolve_with_options<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+    options: ResolveOptions,
+) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+    Solver::new(package, version)
+        .with_options(options)
+        .solve(dependency_provider)
+}
+
+/// Iterate over all the solutions for a given package + version pair.
+///
+/// Each solution is different from the previous ones.
+/// Use [Iterator::take] to only compute the first few.
+pub fn resolve_all<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+) -> Solutions<'_, DP> {
+    Solver::new(package, version).solutions(dependency_provider)
+}
+
+/// Finds the solution with the lowest total cost for a given package + version pair.
+///
+/// The cost of a solution is the sum of the non-negative `cost` of its package + version pairs.
+/// For example, the position of each version from the newest one favors
+/// the newest versions, while a constant cost favors the fewest packages.
+/// See [Solver::optimize] for the details of the search.
+pub fn resolve_optimal<DP: DependencyProvider>(
+    dependency_provider: &DP,
+    package: DP::P,
+    version: impl Into<DP::V>,
+    cost: impl Fn(&DP::P, &DP::V) -> u64,
+) -> Result<Optimum<DP::P, DP::V>, PubGrubError<DP>> {
+    Solver::new(package, version).optimize(dependency_provider, cost, None)
+}
+
+/// A resolution that can be driven one step at a time.
+///
+/// [resolve] runs the whole PubGrub loop at once.
+/// A [Solver] holds the state of that loop instead,
+/// so that the caller can drive it, interleave its own work between steps,
+/// and inspect the partial solution as it evolves.
+/// [solve](Solver::solve) runs the remaining steps with a [DependencyProvider],
+/// exactly like [resolve] does.
+///
+/// A manual resolution loop looks like this:
+///  1. [propagate](Solver::propagate) the consequences of the last change,
+///  2. [pick_package](Solver::pick_package) to decide on next,
+///     or else [pick_alternative](Solver::pick_alternative),
+///     the resolution is done if there is none left,
+///  3. choose a version within its [term](Solver::term_intersection_for_package)
+///     or the versions of the alternative,
+///     or report with [add_no_versions](Solver::add_no_versions) that there are none,
+///  4. if the dependencies of that version are already [known](Solver::has_dependencies),
+///     [add_decision](Solver::add_decision),
+///     otherwise [add_dependencies](Solver::add_dependencies)
+///     or [add_unavailable](Solver::add_unavailable).
+///
+/// When re-resolving after a change, the previous solution can be given as
+/// [preferences](Solver::with_preferences) to keep the locked versions wherever possible.
+pub struct Solver<DP: ProviderTypes> {
+    state: State<DP>,
+    added_dependencies: Map<Id<DP::P>, Set<DP::V>>,
+    /// The packages that retrieved dependencies required, which were given to prefetch.
+    prefetched: crate::Set<Id<DP::P>>,
+    /// The packages whose assignments changed last and still need to be propagated.
+    next: SmallVec<Id<DP::P>>,
+    /// The versions to try first, typically from a lockfile.
+    preferences: SelectedDependencies<DP>,
+    /// The limits of the resolution.
+    options: ResolveOptions,
+    /// The number of conflicts when the search last [restarted](ResolveOptions::restarts).
+    conflicts_at_restart: u64,
+}
+
+// Implemented by hand so that the dependency provider does not need to be cloneable.
+impl<DP: ProviderTypes> Clone for Solver<DP> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            added_dependencies: self.added_dependencies.clone(),
+            prefetched: self.prefetched.clone(),
+            next: self.next.clone(),
+            preferences: self.preferences.clone(),
+            options: self.options.clone(),
+            conflicts_at_restart: self.conflicts_at_restart,
+        }
+    }
+}
+
+impl<DP: ProviderTypes> Solver<DP> {
+    /// Start the resolution of the dependencies of a given package + version pair.
+    pub fn new(package: DP::P, version: impl Into<DP::V>) -> Self {
+        let mut state = State::init(package.clone(), version.into());
+        let root = state.package_store.alloc(package);
+        Self {
+            state,
+            added_dependencies: Map::default(),
+            prefetched: crate::Set::default(),
+            next: SmallVec::one(root),
+            preferences: Map::default(),
+            options: ResolveOptions::default(),
+            conflicts_at_restart: 0,
+        }
+    }
+
+    /// Start the resolution of a set of requirements, without a root package.
+    ///
+    /// See [resolve_requirements] for the meaning of `requirements` and `exclusions`.
+    pub fn from_requirements(
+        requirements: DependencyConstraints<DP::P, DP::VS>,
+        exclusions: DependencyConstraints<DP::P, DP::VS>,
+    ) -> Self {
+        let packages: Vec<_> = requirements
+            .keys()
+            .chain(exclusions.keys())
+            .cloned()
+            .collect();
+        let mut state = State::init_requirements(requirements, exclusions);
+        let mut next = SmallVec::empty();
+        for package in packages {
+            next.push(state.package_store.alloc(package));
+        }
+        Self {
+            state,
+            added_dependencies: Map::default(),
+            prefetched: crate::Set::default(),
+            next,
+            preferences: Map::default(),
+            options: ResolveOptions::default(),
+            conflicts_at_restart: 0,
+        }
+    }
+
+    /// Prefer the versions of a previous solution.
+    ///
+    /// Each preferred version is tried first, before asking the [DependencyProvider],
+    /// and is only given up when the partial solution excludes it.
+    /// Once resolved, [lock_changes](Solver::lock_changes) lists
+    /// the preferred versions that had to move, and why.
+    pub fn with_preferences(mut self, preferences: SelectedDependencies<DP>) -> Self {
+        self.preferences = preferences;
+        self
+    }
+
+    /// Keep the packages of a previous solution at their locked version,
+    /// except the `unlock`ed ones, which can change like the packages that were not locked.
+    ///
+    /// Unlike [preferences](Solver::with_preferences), locked versions are never given up.
+    /// A locked package can still leave the solution if nothing depends on it anymore.
+    /// When a locked version prevents the resolution, the derivation tree explains it with
+    /// [Locked](crate::External::Locked), naming the package to unlock.
+    pub fn with_locked(
+        mut self,
+        locked: &SelectedDependencies<DP>,
+        unlock: &crate::Set<DP::P>,
+    ) -> Self {
+        for (package, version) in locked {
+            if !unlock.contains(package) {
+                self.state.lock(package.clone(), version.clone());
+            }
+        }
+        self
+    }
+
+    /// Start from incompatibilities learned by previous resolutions,
+    /// so that conflicts they already went through are avoided right away.
+    ///
+    /// They must have been learned with a dependency provider giving the same answers
+    /// as the one this resolution uses, see [LearnedIncompatibilities].
+    /// The incompatibilities learned by this resolution then include them,
+    /// to pass them on to the next one.
+    pub fn with_learned_incompatibilities(
+        mut self,
+        learned: &LearnedIncompatibilities<DP::P, DP::VS, DP::M>,
+    ) -> Self {
+        self.state.add_learned_incompatibilities(learned);
+        self
+    }
+
+    /// Set the limits and other options of the resolution, see [ResolveOptions].
+    pub fn with_options(mut self, options: ResolveOptions) -> Self {
+        if options.partial_solution_on_failure {
+            self.state.track_best_decisions();
+        }
+        self.options = options;
+        self
+    }
+
+    /// Fail if the resolution reached one of the limits of its [options](Solver::with_options),
+    /// before the next decision.
+    fn check_limits(&self) -> Result<(), PubGrubError<DP>> {
+        let stats = &self.state.stats;
+        let options = &self.options;
+        let reached = |max: Option<u64>, count: u64| max.is_some_and(|max| count >= max);
+        let limit = if reached(options.max_decisions, stats.decisions) {
+            Limit::Decisions
+        // Conflicts are counted as they happen, so that limit is only reached by going over it.
+        } else if options
+            .max_conflicts
+            .is_some_and(|max| stats.conflicts > max)
+        {
+            Limit::Conflicts
+        } else if reached(
+            options.max_get_dependencies_calls,
+            stats.get_dependencies_calls,
+        ) {
+            Limit::GetDependenciesCalls
+        } else if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Limit::Deadline
+        } else {
+            return Ok(());
+        };
+        info!("resolution stopped: {limit} reached");
+        Err(PubGrubError::LimitReached {
+            limit,
+            stats: stats.clone(),
+        })
+    }
+
+    /// Complete the error of a resolution with the final state of the solver.
+    ///
+    /// The statistics of a [LimitReached](PubGrubError::LimitReached) error are replaced
+    /// by the final ones, including the time spent until the resolution stopped,
+    /// and [NoSolution](PubGrubError::NoSolution) keeps the partial solution if the options ask for it.
+    fn finish<T>(&self, result: Result<T, PubGrubError<DP>>) -> Result<T, PubGrubError<DP>> {
+        result.map_err(|err| match err {
+            PubGrubError::LimitReached { limit, .. } => PubGrubError::LimitReached {
+                limit,
+                stats: self.state.stats.clone(),
+            },
+            PubGrubError::NoSolution(derivation_tree)
+                if self.options.partial_solution_on_failure =>
+            {
+                let involved_packages = derivation_tree.packages().into_iter().cloned().collect();
+                PubGrubError::NoSolutionWithPartial(Box::new(PartialResolution {
+                    derivation_tree,
+                    partial_solution: self.state.best_decisions().cloned().unwrap_or_default(),
+                    involved_packages,
+                }))
+            }
+            err => err,
+        })
+    }
+
+    /// The current time, if the resolution [measures time](ResolveOptions::measure_time).
+    fn now(&self) -> Option<Instant> {
+        self.options.measure_time.then(Instant::now)
+    }
+
+    /// Run resolution steps, counting the time spent outside of the provider as solver time.
+    fn timed<T>(&mut self, run: impl FnOnce(&mut Self) -> T) -> T {
+        let start = self.now();
+        let provider_time = self.state.stats.provider_time;
+        let result = run(self);
+        self.add_solver_time(start, provider_time);
+        result
+    }
+
+    /// Count the time since `start` as solver time,
+    /// except for the time spent in the provider since it was `provider_time`.
+    fn add_solver_time(&mut self, start: Option<Instant>, provider_time: Duration) {
+        if let Some(start) = start {
+            let in_provider = self.state.stats.provider_time - provider_time;
+            self.state.stats.solver_time += start.elapsed().saturating_sub(in_provider);
+        }
+    }
+
+    /// Pick the next package to decide on with the versions it can be chosen from,
+    /// either a [package](Solver::pick_package) required by the partial solution
+    /// or an [alternative](Solver::pick_alternative).
+    #[allow(clippy::type_complexity)]
+    fn pick_next(
+        &mut self,
+        prioritizer: impl Fn(&DP::P, &DP::VS, &PackageResolutionStatistics) -> DP::Priority,
+    ) -> Result<Option<(Id<DP::P>, DP::VS)>, PubGrubError<DP>> {
+        match self.pick_package_id(prioritizer) {
+            Some(next) => {
+                let term_intersection = self
+                    .state
+                    .partial_solution
+                    .term_intersection_for_package(next)
+                    .ok_or_else(|| {
+                        PubGrubError::Failure(
+                            "a package was chosen but we don't have a term.".into(),
+                        )
+                    })?;
+                Ok(Some((next, term_intersection.unwrap_positive().clone())))
+            }
+            None => Ok(self.state.unselected_alternative()),
+        }
+    }
+
+    /// Take the next step of the resolution, given the answer of the dependency provider
+    /// to the previous step if it needed one,
+    /// and return what the dependency provider must do for the resolution to go on.
+    ///
+    /// Without an answer, this [advances](Solver::advance) to the next package to decide on.
+    /// The async and universal resolutions loop over this, and only differ in how they ask
+    /// the dependency provider, while [solve](Solver::solve) advances and
+    /// [decides](Solver::decide) on each package in turn with the same parts.
+    fn step(
+        &mut self,
+        answer: Option<Answer<DP>>,
+        prioritizer: impl Fn(&DP::P, &DP::VS, &PackageResolutionStatistics) -> DP::Priority,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<Step<DP>, PubGrubError<DP>> {
+        if let Some(answer) = answer {
+            return self.answer(answer, observer);
+        }
+        match self.advance(prioritizer, observer)? {
+            Some((next, range)) => self.request_version(next, range, observer),
+            None => Ok(Step::Done),
+        }
+    }
+
+    /// Propagate the last changes and pick the next package to decide on,
+    /// with the versions it can be chosen from, or [None] once the solution is complete.
+    #[allow(clippy::type_complexity)]
+    fn advance(
+        &mut self,
+        prioritizer: impl Fn(&DP::P, &DP::VS, &PackageResolutionStatistics) -> DP::Priority,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<Option<(Id<DP::P>, DP::VS)>, PubGrubError<DP>> {
+        self.propagate_with_observer(observer)?;
+        let next = self.pick_next(prioritizer)?;
+        if next.is_some() {
+            self.check_limits()?;
+        }
+        Ok(next)
+    }
+
+    /// Ask for a version of the picked package,
+    /// unless its preferred version is in range.
+    fn request_version(
+        &mut self,
+        next: Id<DP::P>,
+        range: DP::VS,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<Step<DP>, PubGrubError<DP>> {
+        // A virtual package has the version its decided provider gives it,
+        // without asking the dependency provider.
+        if let Some((_, _, v)) = self.state.provider_of(next) {
+            if range.contains(v) {
+                let v = v.clone();
+                self.decide_on(next, v, observer);
+                return Ok(Step::Propagate);
+            }
+        }
+        let package = &self.state.package_store[next];
+        match self.preferences.get(package).filter(|v| range.contains(v)) {
+            Some(v) => {
+                let v = v.clone();
+                self.answer(Answer::Version(next, range, Ok(Some(v))), observer)
+            }
+            None => Ok(Step::ChooseVersion(next, range)),
+        }
+    }
+
+    /// Add the answer of the dependency provider to the partial solution.
+    fn answer(
+        &mut self,
+        answer: Answer<DP>,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<Step<DP>, PubGrubError<DP>> {
+        match answer {
+            Answer::Version(next, range, decision) => {
+                let decision = decision.map_err(PubGrubError::ErrorChoosingPackageVersion)?;
+                Ok(
+                    match self.add_chosen_version(next, decision, range, observer)? {
+                        Some(v) => Step::GetDependencies(next, v),
+                        None => Step::Propagate,
+                    },
+                )
+            }
+            Answer::Dependencies(next, v, dependencies) => {
+                let dependencies =
+                    dependencies.map_err(|err| PubGrubError::ErrorRetrievingDependencies {
+                        package: self.state.package_store[next].clone(),
+                        version: v.clone(),
+                        source: err,
+                    })?;
+                let new_dependencies = self.new_dependencies(&dependencies);
+                self.add_retrieved_dependencies(next, v, dependencies, observer);
+                Ok(if new_dependencies.is_empty() {
+                    Step::Propagate
+                } else {
+                    Step::Prefetch(new_dependencies)
+                })
+            }
+        }
+    }
+
+    /// Add the version chosen for the picked package to the partial solution,
+    /// returning it if its dependencies need to be retrieved first.
+    fn add_chosen_version(
+        &mut self,
+        next: Id<DP::P>,
+        decision: Option<DP::V>,
+        range: DP::VS,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<Option<DP::V>, PubGrubError<DP>> {
+        info!(
+            "DP chose: {} @ {:?}",
+            self.state.package_store[next], decision
+        );
 
         // Pick the next compatible version.
         let v = match decision {
             None => {
-                let inc = Incompatibility::no_versions(next.clone(), term_intersection.clone());
-                state.add_incompatibility(inc);
-                continue;
+                self.record_no_versions(next, range);
+                return Ok(None);
             }
             Some(x) => x,
         };
 
-        if !term_intersection.contains(&v) {
+        if !range.contains(&v) {
             return Err(PubGrubError::Failure(
                 "choose_package_version picked an incompatible version".into(),
             ));
         }
 
-        let is_new_dependency = added_dependencies
-            .entry(next.clone())
+        if self.dependencies_known(next, &v) {
+            // `dep_incompats` are already in `incompatibilities` so we know there are not satisfied
+            // terms and can add the decision directly.
+            info!(
+                "add_decision (not first time): {} @ {}",
+                self.state.package_store[next], v
+            );
+            self.decide_on(next, v, observer);
+            return Ok(None);
+        }
+        Ok(Some(v))
+    }
+
+    /// The dependencies on packages that no retrieved dependencies required before
+    /// and whose own dependencies were not retrieved yet, which are then marked as prefetched.
+    ///
+    /// Incompatibilities mentioning a package do not matter here,
+    /// as [learned](Solver::with_learned_incompatibilities) ones may come from a previous resolution.
+    fn new_dependencies(
+        &mut self,
+        dependencies: &Dependencies<DP::P, DP::VS, DP::M>,
+    ) -> Vec<(DP::P, DP::VS)> {
+        let required: Vec<(&DP::P, &DP::VS)> = match dependencies {
+            Dependencies::Unavailable(_) => Vec::new(),
+            Dependencies::Available(dependencies) => dependencies.iter().collect(),
+            // Constrained packages are only needed once something else requires them.
+            Dependencies::Extended(dependencies) => dependencies
+                .iter()
+                .flat_map(|dependency| match dependency {
+                    Dependency::Requires(package, range) => vec![(package, range)],
+                    Dependency::Constrains(_, _) => Vec::new(),
+                    Dependency::AnyOf(alternatives) => alternatives
+                        .iter()
+                        .map(|(package, range)| (package, range))
+                        .collect(),
+                    Dependency::Provides(_, _) => Vec::new(),
+                })
+                .collect(),
+        };
+        required
+            .into_iter()
+            .filter(|(package, _)| {
+                let id = self.state.package_store.alloc((*package).clone());
+                !self.added_dependencies.contains_key(&id) && self.prefetched.insert(id)
+            })
+            .map(|(package, range)| (package.clone(), range.clone()))
+            .collect()
+    }
+
+    /// Add the dependencies retrieved for a package + version pair.
+    fn add_retrieved_dependencies(
+        &mut self,
+        package: Id<DP::P>,
+        version: DP::V,
+        dependencies: Dependencies<DP::P, DP::VS, DP::M>,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) {
+        match dependencies {
+            Dependencies::Unavailable(reason) => self.record_unavailable(package, version, reason),
+            Dependencies::Available(x) => self.record_dependencies(
+                package,
+                version,
+                x.into_iter()
+                    .map(|(p, range)| Dependency::Requires(p, range)),
+                observer,
+            ),
+            Dependencies::Extended(x) => self.record_dependencies(package, version, x, observer),
+        }
+    }
+
+    /// Forbid the current decisions from being selected all together again,
+    /// so that resolving further finds a different solution.
+    ///
+    /// This records an incompatibility made of all the decisions,
+    /// and the search continues from there, keeping everything it learned.
+    /// Without any decision there is nothing to exclude, and this does nothing.
+    pub fn exclude_solution(&mut self) {
+        let decisions: Vec<_> = self
+            .state
+            .partial_solution
+            .decisions()
+            .map(|(p, v)| (p, v.clone()))
+            .collect();
+        self.prune(decisions);
+    }
+
+    /// Forbid a set of decisions of the partial solution from being selected all together.
+    fn prune(&mut self, decisions: Vec<(Id<DP::P>, DP::V)>) {
+        if decisions.is_empty() {
+            return;
+        }
+        if let Some(package) = self
+            .state
+            .add_satisfied_incompatibility(Incompatibility::pruned(decisions))
+        {
+            self.next = SmallVec::one(package);
+        }
+    }
+
+    /// Derive everything that follows from the last change to the partial solution,
+    /// performing conflict resolution and backtracking when needed.
+    ///
+    /// Fails if the conflict cannot be resolved, in which case there is no solution.
+    pub fn propagate(&mut self) -> Result<(), NoSolutionError<DP>> {
+        self.propagate_with_observer(&())
+    }
+
+    /// Same as [propagate](Solver::propagate), reporting the steps of the resolution to an [Observer].
+    pub fn propagate_with_observer(
+        &mut self,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<(), NoSolutionError<DP>> {
+        while let Some(package) = self.next.pop() {
+            info!("unit_propagation: {}", self.state.package_store[package]);
+            self.state.unit_propagation(package, observer)?;
+        }
+        self.restart_if_due(observer);
+        debug!(
+            "Partial solution after unit propagation: {}",
+            self.state
+                .partial_solution
+                .display(&self.state.package_store)
+        );
+        Ok(())
+    }
+
+    /// Restart the search if the [restart policy](ResolveOptions::restarts)
+    /// allows no more conflicts since the last restart.
+    ///
+    /// The partial solution is then backtracked to its first decision level,
+    /// which is fully propagated already, so there is nothing left to propagate.
+    fn restart_if_due(&mut self, observer: &impl Observer<DP::P, DP::VS>) {
+        let Some(policy) = &self.options.restarts else {
+            return;
+        };
+        let conflicts = self.state.stats.conflicts - self.conflicts_at_restart;
+        if conflicts < policy.conflicts_before_restart(self.state.stats.restarts) {
+            return;
+        }
+        self.conflicts_at_restart = self.state.stats.conflicts;
+        if self.state.restart(observer) {
+            info!("restart after {} conflicts", conflicts);
+        }
+    }
+
+    /// Pick the package with the highest priority among the packages
+    /// that are required by the partial solution but have no decision yet.
+    ///
+    /// Returns [None] when all required packages have a decision,
+    /// meaning that the [solution](Solver::solution) is complete.
+    /// It must only be called after the last change has been [propagated](Solver::propagate).
+    pub fn pick_package(
+        &mut self,
+        prioritizer: impl Fn(&DP::P, &DP::VS, &PackageResolutionStatistics) -> DP::Priority,
+    ) -> Option<DP::P> {
+        let next = self.pick_package_id(prioritizer)?;
+        Some(self.state.package_store[next].clone())
+    }
+
+    fn pick_package_id(
+        &mut self,
+        prioritizer: impl Fn(&DP::P, &DP::VS, &PackageResolutionStatistics) -> DP::Priority,
+    ) -> Option<Id<DP::P>> {
+        let package_store = &self.state.package_store;
+        let package_statistics = &self.state.package_statistics;
+        self.state
+            .partial_solution
+            .pick_highest_priority_pkg(|p, r| {
+                let statistics = package_statistics.get(&p).copied().unwrap_or_default();
+                prioritizer(&package_store[p], r, &statistics)
+            })
+    }
+
+    /// Pick an alternative of a dependency on [any of](Dependency::AnyOf) several packages,
+    /// when the dependent is selected but none of the alternatives is,
+    /// along with the versions it can be chosen from.
+    ///
+    /// Such alternatives are not required by the partial solution on their own,
+    /// so they are only picked once [pick_package](Solver::pick_package) has nothing left.
+    /// The chosen version is then added like for any other package.
+    pub fn pick_alternative(&self) -> Option<(DP::P, DP::VS)> {
+        let (package, range) = self.state.unselected_alternative()?;
+        Some((self.state.package_store[package].clone(), range))
+    }
+
+    /// Intersection of all the terms of the partial solution related to a package.
+    ///
+    /// For a package returned by [pick_package](Solver::pick_package),
+    /// this is a positive term containing all the versions that can still be chosen.
+    pub fn term_intersection_for_package(&self, package: &DP::P) -> Option<&Term<DP::VS>> {
+        let package = self.state.package_store.get_id(package)?;
+        self.state
+            .partial_solution
+            .term_intersection_for_package(package)
+    }
+
+    /// The [preferred](Solver::with_preferences) version of a package,
+    /// if the partial solution still allows it.
+    pub fn preferred_version(&self, package: &DP::P) -> Option<&DP::V> {
+        let version = self.preferences.get(package)?;
+        self.term_intersection_for_package(package)?
+            .contains(version)
+            .then_some(version)
+    }
+
+    /// Record that there is no version of the package in the given range.
+    pub fn add_no_versions(&mut self, package: DP::P, range: DP::VS) {
+        let package = self.state.package_store.alloc(package);
+        self.record_no_versions(package, range);
+    }
+
+    fn record_no_versions(&mut self, package: Id<DP::P>, range: DP::VS) {
+        self.state
+            .add_incompatibility(Incompatibility::no_versions(package, Term::Positive(range)));
+        self.next = SmallVec::one(package);
+    }
+
+    /// Check if the dependencies of that package + version pair have already been added,
+    /// in which case a decision can directly be made with [add_decision](Solver::add_decision).
+    pub fn has_dependencies(&self, package: &DP::P, version: &DP::V) -> bool {
+        self.state
+            .package_store
+            .get_id(package)
+            .is_some_and(|package| self.dependencies_known(package, version))
+    }
+
+    fn dependencies_known(&self, package: Id<DP::P>, version: &DP::V) -> bool {
+        self.added_dependencies
+            .get(&package)
+            .is_some_and(|versions| versions.contains(version))
+    }
+
+    /// Decide on a version of a package whose dependencies have already been added.
+    ///
+    /// The version must be allowed by the [term](Solver::term_intersection_for_package)
+    /// of the package, otherwise the partial solution is left unchanged
+    /// and [InvalidDecision](PubGrubError::InvalidDecision) is returned.
+    pub fn add_decision(&mut self, package: DP::P, version: DP::V) -> Result<(), PubGrubError<DP>> {
+        let valid = self.state.package_store.get_id(&package).filter(|&id| {
+            self.dependencies_known(id, &version)
+                && self.state.partial_solution.decision(id).is_none()
+                && self
+                    .state
+                    .partial_solution
+                    .term_intersection_for_package(id)
+                    .is_none_or(|term| term.contains(&version))
+        });
+        let Some(package) = valid else {
+            return Err(PubGrubError::InvalidDecision { package, version });
+        };
+        self.decide_on(package, version, &());
+        Ok(())
+    }
+
+    fn decide_on(
+        &mut self,
+        package: Id<DP::P>,
+        version: DP::V,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) {
+        debug_assert!(
+            self.dependencies_known(package, &version) || self.state.provider_of(package).is_some()
+        );
+        observer.decision(&self.state.package_store[package], &version);
+        self.state.partial_solution.add_decision(package, version);
+        self.state.stats.decisions += 1;
+        self.next = SmallVec::one(package);
+    }
+
+    /// Record the dependencies of a package + version pair,
+    /// and decide on that version if they don't conflict with the partial solution.
+    pub fn add_dependencies(
+        &mut self,
+        package: DP::P,
+        version: DP::V,
+        dependencies: impl IntoIterator<Item = (DP::P, DP::VS)>,
+    ) {
+        self.add_extended_dependencies(
+            package,
+            version,
+            dependencies
+                .into_iter()
+                .map(|(p, range)| Dependency::Requires(p, range)),
+        );
+    }
+
+    /// Record the dependencies of a package + version pair, including constraints,
+    /// and decide on that version if they don't conflict with the partial solution.
+    pub fn add_extended_dependencies(
+        &mut self,
+        package: DP::P,
+        version: DP::V,
+        dependencies: impl IntoIterator<Item = Dependency<DP::P, DP::VS>>,
+    ) {
+        let package = self.state.package_store.alloc(package);
+        self.record_dependencies(package, version, dependencies, &());
+    }
+
+    fn record_dependencies(
+        &mut self,
+        package: Id<DP::P>,
+        version: DP::V,
+        dependencies: impl IntoIterator<Item = Dependency<DP::P, DP::VS>>,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) {
+        // Add that package and version if the dependencies are not problematic.
+        let dep_incompats = self.state.add_incompatibility_from_dependencies(
+            package,
+            version.clone(),
+            dependencies,
+        );
+        let conflict = self.state.partial_solution.add_version(
+            package,
+            version.clone(),
+            dep_incompats,
+            &self.state.incompatibility_store,
+            &self.state.package_store,
+        );
+        match conflict {
+            Some(conflict) => self.state.dependency_conflict(package, conflict),
+            None => {
+                self.state.stats.decisions += 1;
+                observer.decision(&self.state.package_store[package], &version);
+            }
+        }
+        self.added_dependencies
+            .entry(package)
             .or_default()
-            .insert(v.clone());
-
-        if is_new_dependency {
-            // Retrieve that package dependencies.
-            let p = &next;
-            let dependencies = dependency_provider.get_dependencies(p, &v).map_err(|err| {
-                PubGrubError::ErrorRetrievingDependencies {
-                    package: p.clone(),
-                    version: v.clone(),
-                    source: err,
+            .insert(version);
+        self.next = SmallVec::one(package);
+    }
+
+    /// Record that the dependencies of a package + version pair are unavailable,
+    /// which makes that version unusable.
+    pub fn add_unavailable(&mut self, package: DP::P, version: DP::V, reason: DP::M) {
+        let package = self.state.package_store.alloc(package);
+        self.record_unavailable(package, version, reason);
+    }
+
+    fn record_unavailable(&mut self, package: Id<DP::P>, version: DP::V, reason: DP::M) {
+        self.added_dependencies
+            .entry(package)
+            .or_default()
+            .insert(version.clone());
+        self.state
+            .add_incompatibility(Incompatibility::custom_version(package, version, reason));
+        self.next = SmallVec::one(package);
+    }
+
+    /// Counters of what happened during the resolution so far.
+    pub fn stats(&self) -> &ResolutionStats {
+        &self.state.stats
+    }
+
+    /// The incompatibilities learned so far that do not depend on what was asked to resolve,
+    /// to [reuse](Solver::with_learned_incompatibilities) in other resolutions.
+    ///
+    /// This works whether the resolution succeeded or failed.
+    pub fn learned_incompatibilities(&self) -> LearnedIncompatibilities<DP::P, DP::VS, DP::M> {
+        self.state.learned_incompatibilities()
+    }
+
+    /// Iterate over the decisions of the partial solution, in the order they were made.
+    pub fn decisions(&self) -> impl Iterator<Item = (&DP::P, &DP::V)> {
+        let package_store = &self.state.package_store;
+        self.state
+            .partial_solution
+            .decisions()
+            .map(move |(package, version)| (&package_store[package], version))
+    }
+
+    /// All the decisions of the partial solution.
+    ///
+    /// This is the complete solution once [pick_package](Solver::pick_package)
+    /// and [pick_alternative](Solver::pick_alternative) have no package left to decide on.
+    ///
+    /// A virtual package decided on the version that a decided package
+    /// [provides](Dependency::Provides) is left out, the package providing it stands in for it.
+    pub fn solution(&self) -> SelectedDependencies<DP> {
+        let mut solution = self
+            .state
+            .partial_solution
+            .extract_solution(&self.state.package_store);
+        for virtual_package in self.providers().keys() {
+            solution.remove(virtual_package);
+        }
+        solution
+    }
+
+    /// The dependencies between the decided packages, in the order of the decisions.
+    ///
+    /// Once the [solution](Solver::solution) is complete, these are the edges
+    /// of its dependency graph, without calling
+    /// [get_dependencies](DependencyProvider::get_dependencies) again.
+    /// A dependency on [any of](Dependency::AnyOf) several packages leads to its first selected
+    /// alternative, and a virtual package to its [provider](Solver::providers).
+    /// [Constraints](Dependency::Constrains) are not edges.
+    pub fn dependency_edges(&self) -> Vec<DependencyEdge<DP::P, DP::VS>> {
+        self.state
+            .dependency_edges()
+            .into_iter()
+            .map(|(dependent, dependency, range)| DependencyEdge {
+                dependent: self.state.package_store[dependent].clone(),
+                dependency: self.state.package_store[dependency].clone(),
+                range: range.clone(),
+            })
+            .collect()
+    }
+
+    /// Why a package is decided on, or [None] if it is not.
+    ///
+    /// After a successful resolution, this explains why a package of the solution was selected,
+    /// through the chain of dependencies and decisions that introduced it and narrowed its range.
+    pub fn why_selected(&self, package: &DP::P) -> Option<SelectionReason<DP::P, DP::VS, DP::M>> {
+        let steps = self.state.selection_steps(package)?;
+        Some(SelectionReason { steps })
+    }
+
+    /// Why the versions of a package newer than the decided one were not chosen,
+    /// or [None] if that package is not decided on.
+    ///
+    /// The solver does not know which versions exist,
+    /// so they are given by the caller, for example all the versions of the package
+    /// in the dependency provider. Versions not newer than the decided one are skipped.
+    #[allow(clippy::type_complexity)]
+    pub fn why_not_newer(
+        &self,
+        package: &DP::P,
+        versions: impl IntoIterator<Item = DP::V>,
+    ) -> Option<Vec<NewerVersion<DP::P, DP::VS, DP::M>>> {
+        let package_id = self.state.package_store.get_id(package)?;
+        let selected = self.state.partial_solution.decision(package_id)?;
+        let newer = versions
+            .into_iter()
+            .filter(|version| version > selected)
+            .map(|version| {
+                let reason = self.state.explain_exclusion(package, &version);
+                NewerVersion { version, reason }
+            })
+            .collect();
+        Some(newer)
+    }
+
+    /// For each decided virtual package, the decided package version that
+    /// [provides](Dependency::Provides) the version it is decided on.
+    ///
+    /// These virtual packages are not part of the [solution](Solver::solution),
+    /// where their providers stand in for them.
+    pub fn providers(&self) -> Map<DP::P, (DP::P, DP::V)> {
+        let partial_solution = &self.state.partial_solution;
+        let package_store = &self.state.package_store;
+        partial_solution
+            .decisions()
+            .filter_map(|(package, _)| {
+                let (provider, _) = self.state.decided_provider(package)?;
+                let provider_version = partial_solution.decision(provider)?;
+                Some((
+                    package_store[package].clone(),
+                    (package_store[provider].clone(), provider_version.clone()),
+                ))
+            })
+            .collect()
+    }
+
+    /// The [preferred](Solver::with_preferences) versions that the solution does not keep.
+    ///
+    /// A package that is not part of the solution anymore has no reason attached.
+    /// Otherwise, the reason is derived from the incompatibilities
+    /// that excluded the preferred version.
+    pub fn lock_changes(&self) -> Vec<LockChange<DP::P, DP::VS, DP::M>> {
+        let solution = self.solution();
+        self.preferences
+            .iter()
+            .filter(|(package, locked)| solution.get(*package) != Some(*locked))
+            .map(|(package, locked)| {
+                let selected = solution.get(package).cloned();
+                let reason = selected
+                    .as_ref()
+                    .and_then(|_| self.state.explain_exclusion(package, locked));
+                LockChange {
+                    package: package.clone(),
+                    locked: locked.clone(),
+                    selected,
+                    reason,
                 }
-            })?;
+            })
+            .collect()
+    }
+}
 
-            let dependencies = match dependencies {
-                Dependencies::Unavailable(reason) => {
-                    state.add_incompatibility(Incompatibility::custom_version(
-                        p.clone(),
-                        v.clone(),
-                        reason,
-                    ));
+impl<DP: DependencyProvider> Solver<DP> {
+    /// Run the remaining resolution steps until a solution is found
+    /// or the resolution fails.
+    pub fn solve(
+        &mut self,
+        dependency_provider: &DP,
+    ) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+        self.solve_with_observer(dependency_provider, &())
+    }
+
+    /// Same as [solve](Solver::solve), reporting the steps of the resolution to an [Observer].
+    pub fn solve_with_observer(
+        &mut self,
+        dependency_provider: &DP,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+        let result = self.timed(|solver| loop {
+            dependency_provider
+                .should_cancel()
+                .map_err(PubGrubError::ErrorInShouldCancel)?;
+
+            let Some((next, range)) =
+                solver.advance(|p, r, s| dependency_provider.prioritize(p, r, s), observer)?
+            else {
+                return Ok(solver.solution());
+            };
+            solver.decide(dependency_provider, next, range, observer)?;
+        });
+        self.finish(result)
+    }
+
+    /// Ask the dependency provider what a step of the resolution needs,
+    /// returning its answer if the step needs one.
+    fn fetch(&mut self, dependency_provider: &DP, step: Step<DP>) -> Option<Answer<DP>> {
+        let start = self.now();
+        let answer = match step {
+            Step::ChooseVersion(next, range) => {
+                let package = &self.state.package_store[next];
+                let decision = dependency_provider.choose_version(package, &range);
+                Some(Answer::Version(next, range, decision))
+            }
+            Step::GetDependencies(next, v) => {
+                let package = &self.state.package_store[next];
+                let dependencies = dependency_provider.get_dependencies(package, &v);
+                self.state.stats.get_dependencies_calls += 1;
+                Some(Answer::Dependencies(next, v, dependencies))
+            }
+            Step::Prefetch(dependencies) => {
+                dependency_provider.prefetch(&dependencies);
+                None
+            }
+            Step::Propagate | Step::Done => None,
+        };
+        self.state.stats.add_provider_time(start);
+        answer
+    }
+
+    /// Choose a version of the picked package and add it to the partial solution,
+    /// asking the dependency provider until the decision needs nothing more from it.
+    fn decide(
+        &mut self,
+        dependency_provider: &DP,
+        next: Id<DP::P>,
+        range: DP::VS,
+        observer: &impl Observer<DP::P, DP::VS>,
+    ) -> Result<(), PubGrubError<DP>> {
+        let mut step = self.request_version(next, range, observer)?;
+        while let Some(answer) = self.fetch(dependency_provider, step) {
+            step = self.answer(answer, observer)?;
+        }
+        Ok(())
+    }
+
+    /// Iterate over the solutions that remain to be found, see [Solutions].
+    pub fn solutions(self, dependency_provider: &DP) -> Solutions<'_, DP> {
+        Solutions {
+            solver: self,
+            dependency_provider,
+            found: false,
+            done: false,
+        }
+    }
+
+    /// Find the solution with the lowest total cost,
+    /// where the cost of a solution is the sum of the costs of its package + version pairs.
+    ///
+    /// This is a branch-and-bound search on top of conflict learning:
+    /// every time a solution is found, all partial solutions that cost
+    /// at least as much are [pruned](crate::External::Pruned),
+    /// and the search continues until there is nothing left to explore.
+    /// As costs are non-negative, a partial solution can be pruned
+    /// as soon as its decisions alone cost as much as the best solution.
+    ///
+    /// With a `budget`, the search stops after that many decisions
+    /// once a solution is known, and returns the best one found so far.
+    /// Reaching a limit of the [options](Solver::with_options) does the same,
+    /// or fails if no solution is known yet.
+    /// [proven_optimal](Optimum::proven_optimal) tells whether the search completed.
+    pub fn optimize(
+        &mut self,
+        dependency_provider: &DP,
+        cost: impl Fn(&DP::P, &DP::V) -> u64,
+        budget: Option<u64>,
+    ) -> Result<Optimum<DP::P, DP::V>, PubGrubError<DP>> {
+        let result = self.timed(|solver| {
+            let mut best: Option<Optimum<DP::P, DP::V>> = None;
+            let mut decisions_left = budget;
+            loop {
+                dependency_provider
+                    .should_cancel()
+                    .map_err(PubGrubError::ErrorInShouldCancel)?;
+
+                if let Err(err) = solver.propagate() {
+                    return match best {
+                        // Everything cheaper than the best solution has been ruled out.
+                        Some(best) => Ok(Optimum {
+                            proven_optimal: true,
+                            ..best
+                        }),
+                        None => Err(PubGrubError::NoSolution(err)),
+                    };
+                }
+
+                if let Some(best) = &best {
+                    if best.cost == 0 {
+                        // Nothing can be cheaper than free.
+                        return Ok(Optimum {
+                            proven_optimal: true,
+                            ..best.clone()
+                        });
+                    }
+                    if decisions_left == Some(0) {
+                        return Ok(best.clone());
+                    }
+                    // Only decisions with a cost contribute to the bound,
+                    // leaving them out makes for a more general incompatibility.
+                    let mut current_cost = 0;
+                    let costly: Vec<_> = solver
+                        .state
+                        .partial_solution
+                        .decisions()
+                        .filter_map(|(p, v)| {
+                            let c = cost(&solver.state.package_store[p], v);
+                            current_cost += c;
+                            (c > 0).then(|| (p, v.clone()))
+                        })
+                        .collect();
+                    if current_cost >= best.cost {
+                        debug!("prune partial solution costing {}", current_cost);
+                        solver.prune(costly);
+                        continue;
+                    }
+                }
+
+                let Some((next, range)) =
+                    solver.pick_next(|p, r, s| dependency_provider.prioritize(p, r, s))?
+                else {
+                    let solution = solver.solution();
+                    let solution_cost = solution.iter().map(|(p, v)| cost(p, v)).sum();
+                    info!("found a solution costing {}", solution_cost);
+                    best = Some(Optimum {
+                        solution,
+                        cost: solution_cost,
+                        proven_optimal: false,
+                    });
                     continue;
+                };
+
+                if let Err(err) = solver.check_limits() {
+                    return best.ok_or(err);
                 }
-                Dependencies::Available(x) => x,
+                if best.is_some() {
+                    decisions_left = decisions_left.map(|left| left - 1);
+                }
+                solver.decide(dependency_provider, next, range, &())?;
+            }
+        });
+        self.finish(result)
+    }
+}
+
+impl<DP: AsyncDependencyProvider> Solver<DP> {
+    /// Run the remaining resolution steps like [solve](Solver::solve),
+    /// awaiting the [AsyncDependencyProvider] instead of blocking on it.
+    pub async fn solve_async(
+        &mut self,
+        dependency_provider: &DP,
+    ) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+        let start = self.now();
+        let provider_time = self.state.stats.provider_time;
+        let result = self.run_async(dependency_provider).await;
+        self.add_solver_time(start, provider_time);
+        self.finish(result)
+    }
+
+    async fn run_async(
+        &mut self,
+        dependency_provider: &DP,
+    ) -> Result<SelectedDependencies<DP>, PubGrubError<DP>> {
+        let mut answer = None;
+        loop {
+            if answer.is_none() {
+                dependency_provider
+                    .should_cancel()
+                    .map_err(PubGrubError::ErrorInShouldCancel)?;
+            }
+
+            let step = self.step(
+                answer.take(),
+                |p, r, s| dependency_provider.prioritize(p, r, s),
+                &(),
+            )?;
+            if let Step::Done = step {
+                return Ok(self.solution());
+            }
+            answer = self.fetch_async(dependency_provider, step).await;
+        }
+    }
+
+    /// Same as [fetch](Solver::fetch), awaiting the dependency provider.
+    async fn fetch_async(
+        &mut self,
+        dependency_provider: &DP,
+        step: Step<DP>,
+    ) -> Option<Answer<DP>> {
+        let start = self.now();
+        let answer = match step {
+            Step::ChooseVersion(next, range) => {
+                let package = &self.state.package_store[next];
+                let decision = dependency_provider.choose_version(package, &range).await;
+                Some(Answer::Version(next, range, decision))
+            }
+            Step::GetDependencies(next, v) => {
+                let package = &self.state.package_store[next];
+                let dependencies = dependency_provider.get_dependencies(package, &v).await;
+                self.state.stats.get_dependencies_calls += 1;
+                Some(Answer::Dependencies(next, v, dependencies))
+            }
+            Step::Prefetch(dependencies) => {
+                dependency_provider.prefetch(&dependencies);
+                None
+            }
+            Step::Propagate | Step::Done => None,
+        };
+        self.state.stats.add_provider_time(start);
+        answer
+    }
+}
+
+impl<DP: EnvironmentDependencyProvider> Solver<DP> {
+    /// Run the remaining resolution steps for several environments at once.
+    ///
+    /// All the environments start in a single resolution.
+    /// When the environments of a resolution disagree on the dependencies of a package version,
+    /// the resolution forks: a copy of the solver continues for each group of environments
+    /// with the same dependencies.
+    /// Fails if any of the forks has no solution.
+    #[allow(clippy::type_complexity)]
+    pub fn solve_universal(
+        self,
+        dependency_provider: &DP,
+        environments: impl IntoIterator<Item = DP::Environment>,
+    ) -> Result<UniversalSolution<DP::P, DP::V, DP::Environment>, PubGrubError<DP>> {
+        let environments: Vec<_> = environments.into_iter().collect();
+        let mut forks = Vec::new();
+        if !environments.is_empty() {
+            forks.push((environments, self, None));
+        }
+        let mut solutions = Vec::new();
+        while let Some((mut environments, mut solver, mut answer)) = forks.pop() {
+            let result = solver.timed(|solver| loop {
+                if answer.is_none() {
+                    dependency_provider
+                        .should_cancel()
+                        .map_err(PubGrubError::ErrorInShouldCancel)?;
+                }
+
+                let step = solver.step(
+                    answer.take(),
+                    |p, r, s| dependency_provider.prioritize(p, r, s),
+                    &(),
+                )?;
+                answer = match step {
+                    Step::Done => return Ok(solver.solution()),
+                    Step::GetDependencies(next, v) => {
+                        let (dependencies, new_forks) = solver.fork_on_dependencies(
+                            dependency_provider,
+                            &mut environments,
+                            next,
+                            &v,
+                        );
+                        forks.extend(new_forks);
+                        Some(Answer::Dependencies(next, v, dependencies))
+                    }
+                    step => solver.fetch(dependency_provider, step),
+                };
+            });
+            let solution = solver.finish(result)?;
+            info!("resolved {} environments", environments.len());
+            solutions.push((environments, solution));
+        }
+        Ok(UniversalSolution { forks: solutions })
+    }
+
+    /// Retrieve the dependencies of the chosen version of a package in each environment.
+    ///
+    /// The environments in which the dependencies differ from the first ones are split off
+    /// in new forks, which are returned along with the dependencies they go on with.
+    #[allow(clippy::type_complexity)]
+    fn fork_on_dependencies(
+        &mut self,
+        dependency_provider: &DP,
+        environments: &mut Vec<DP::Environment>,
+        next: Id<DP::P>,
+        v: &DP::V,
+    ) -> (
+        Result<Dependencies<DP::P, DP::VS, DP::M>, DP::Err>,
+        Vec<(Vec<DP::Environment>, Self, Option<Answer<DP>>)>,
+    ) {
+        let package = &self.state.package_store[next];
+
+        // Group the environments by the dependencies of that package in each of them.
+        let mut groups: Vec<(Dependencies<DP::P, DP::VS, DP::M>, Vec<DP::Environment>)> =
+            Vec::new();
+        for environment in environments.drain(..) {
+            let start = self.now();
+            let dependencies =
+                dependency_provider.get_environment_dependencies(package, v, &environment);
+            self.state.stats.add_provider_time(start);
+            self.state.stats.get_dependencies_calls += 1;
+            let dependencies = match dependencies {
+                Ok(dependencies) => dependencies,
+                Err(err) => return (Err(err), Vec::new()),
             };
+            match groups.iter_mut().find(|(group, _)| group == &dependencies) {
+                Some((_, group_environments)) => group_environments.push(environment),
+                None => groups.push((dependencies, vec![environment])),
+            }
+        }
 
-            // Add that package and version if the dependencies are not problematic.
-            let dep_incompats =
-                state.add_incompatibility_from_dependencies(p.clone(), v.clone(), dependencies);
+        let mut groups = groups.into_iter();
+        let (dependencies, first_environments) = groups.next().unwrap();
+        let forks = groups
+            .map(|(dependencies, group_environments)| {
+                info!(
+                    "fork on the dependencies of {} {} for {} environments",
+                    self.state.package_store[next],
+                    v,
+                    group_environments.len()
+                );
+                let answer = Answer::Dependencies(next, v.clone(), Ok(dependencies));
+                (group_environments, self.clone(), Some(answer))
+            })
+            .collect();
+        *environments = first_environments;
+        (Ok(dependencies), forks)
+    }
+}
 
-            state.partial_solution.add_version(
-                p.clone(),
-                v.clone(),
-                dep_incompats,
-                &state.incompatibility_store,
-            );
-        } else {
-            // `dep_incompats` are already in `incompatibilities` so we know there are not satisfied
-            // terms and can add the decision directly.
-            info!("add_decision (not first time): {} @ {}", &next, v);
-            state.partial_solution.add_decision(next.clone(), v);
+/// What the resolution needs from the dependency provider to go on, see [Solver::step].
+enum Step<DP: ProviderTypes> {
+    /// Choose a version of the package in the range.
+    ChooseVersion(Id<DP::P>, DP::VS),
+    /// Retrieve the dependencies of the chosen version of the package.
+    GetDependencies(Id<DP::P>, DP::V),
+    /// Nothing, but the packages of these new dependencies can be prefetched.
+    Prefetch(Vec<(DP::P, DP::VS)>),
+    /// Nothing, the partial solution changed and the next step propagates it.
+    Propagate,
+    /// Nothing, the solution is complete.
+    Done,
+}
+
+/// What the dependency provider answered to a [Step].
+#[allow(clippy::type_complexity)]
+enum Answer<DP: ProviderTypes> {
+    /// The version chosen for [ChooseVersion](Step::ChooseVersion).
+    Version(Id<DP::P>, DP::VS, Result<Option<DP::V>, DP::Err>),
+    /// The dependencies retrieved for [GetDependencies](Step::GetDependencies).
+    Dependencies(
+        Id<DP::P>,
+        DP::V,
+        Result<Dependencies<DP::P, DP::VS, DP::M>, DP::Err>,
+    ),
+}
+
+/// Iterator over successive distinct solutions, created by [resolve_all] or [Solver::solutions].
+///
+/// Once a solution is found, it is [excluded](Solver::exclude_solution)
+/// and the same search continues to find the next one.
+/// The iterator ends when there is no solution left,
+/// or yields the error if there is no solution at all or if resolution failed.
+pub struct Solutions<'a, DP: DependencyProvider> {
+    solver: Solver<DP>,
+    dependency_provider: &'a DP,
+    found: bool,
+    done: bool,
+}
+
+impl<DP: DependencyProvider> Iterator for Solutions<'_, DP> {
+    type Item = Result<SelectedDependencies<DP>, PubGrubError<DP>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.found {
+            self.solver.exclude_solution();
+        }
+        match self.solver.solve(self.dependency_provider) {
+            Ok(solution) => {
+                // An empty solution cannot be excluded without excluding everything.
+                self.done = solution.is_empty();
+                self.found = true;
+                Some(Ok(solution))
+            }
+            Err(PubGrubError::NoSolution(_) | PubGrubError::NoSolutionWithPartial(_))
+                if self.found =>
+            {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
         }
     }
 }
 
+/// Statistics about a resolution, from [resolve_with_stats] or [Solver::stats].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolutionStats {
+    /// Number of versions that were decided on, including those later backtracked.
+    pub decisions: u64,
+    /// Number of terms derived by unit propagation.
+    pub unit_propagations: u64,
+    /// Number of times an incompatibility was satisfied by the partial solution.
+    pub conflicts: u64,
+    /// Number of times the partial solution was backtracked.
+    pub backtracks: u64,
+    /// Number of times the search [restarted](ResolveOptions::restarts),
+    /// which are also counted as backtracks.
+    pub restarts: u64,
+    /// Number of incompatibilities learned from conflicts.
+    pub learned_incompatibilities: u64,
+    /// Number of calls to [get_dependencies](DependencyProvider::get_dependencies).
+    pub get_dependencies_calls: u64,
+    /// Time spent choosing versions and retrieving dependencies in the provider,
+    /// only if the resolution [measures time](ResolveOptions::measure_time).
+    pub provider_time: Duration,
+    /// Time spent resolving, outside of the provider,
+    /// only if the resolution [measures time](ResolveOptions::measure_time).
+    pub solver_time: Duration,
+}
+
+impl ResolutionStats {
+    /// Count the time since `start` as provider time, if it was measured.
+    fn add_provider_time(&mut self, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.provider_time += start.elapsed();
+        }
+    }
+}
+
+/// How often a package was involved in conflicts during a resolution so far,
+/// given to [prioritize](DependencyProvider::prioritize).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PackageResolutionStatistics {
+    /// Number of conflicts found by unit propagation
+    /// in which the package was the last one to be assigned.
+    pub unit_propagation_affected: u32,
+    /// Number of conflicts found by unit propagation
+    /// in which the package was involved, but was not the last one to be assigned.
+    pub unit_propagation_culprit: u32,
+    /// Number of times a version of the package was not decided on
+    /// because its dependencies conflicted with the partial solution.
+    pub dependencies_affected: u32,
+    /// Number of times a dependency on the package prevented a decision on another package.
+    pub dependencies_culprit: u32,
+}
+
+impl PackageResolutionStatistics {
+    /// The number of conflicts the package was involved in, in any way.
+    pub fn conflict_count(&self) -> u32 {
+        self.unit_propagation_affected
+            + self.unit_propagation_culprit
+            + self.dependencies_affected
+            + self.dependencies_culprit
+    }
+}
+
+/// Limits and other options of a resolution, for [resolve_with_options] or [Solver::with_options].
+///
+/// No limit is set by default.
+/// The limits are checked before each decision,
+/// and the resolution fails with [PubGrubError::LimitReached] once one of them is reached.
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Maximum number of [decisions](ResolutionStats::decisions).
+    pub max_decisions: Option<u64>,
+    /// Maximum number of [conflicts](ResolutionStats::conflicts).
+    ///
+    /// Conflicts are only noticed after they happen,
+    /// so the resolution stops at the first decision that follows one conflict too many.
+    pub max_conflicts: Option<u64>,
+    /// Maximum number of calls to [get_dependencies](DependencyProvider::get_dependencies).
+    pub max_get_dependencies_calls: Option<u64>,
+    /// Point in time after which the resolution stops.
+    pub deadline: Option<Instant>,
+    /// Fail with [PubGrubError::NoSolutionWithPartial] instead of [PubGrubError::NoSolution],
+    /// to keep the most decisions that were consistent with each other during the resolution.
+    pub partial_solution_on_failure: bool,
+    /// Measure the [provider](ResolutionStats::provider_time)
+    /// and [solver](ResolutionStats::solver_time) time, which [resolve_with_stats] does.
+    ///
+    /// This reads the clock around every call to the provider, so it is off by default.
+    pub measure_time: bool,
+    /// Restart the search from the first decision level every so many conflicts.
+    ///
+    /// A restart undoes all the decisions but keeps the learned incompatibilities.
+    /// By default, the search never restarts.
+    ///
+    /// Restarts are experimental, see [RestartPolicy].
+    pub restarts: Option<RestartPolicy>,
+}
+
+/// When to [restart](ResolveOptions::restarts) the search, in number of conflicts.
+///
+/// **Experimental:** no known workload resolves faster or with fewer conflicts with restarts,
+/// on the large_case benchmark they never happen or make the resolution slower.
+/// The policies, or restarts altogether, may change or go away in a minor version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Restart after `unit` times the terms of the Luby sequence (1, 1, 2, 1, 1, 2, 4, 1, ...)
+    /// conflicts since the last restart.
+    ///
+    /// The runs between restarts get longer and longer,
+    /// so that resolutions that need many conflicts still complete.
+    Luby {
+        /// The number of conflicts for a term of 1, zero counting as 1.
+        unit: u64,
+    },
+}
+
+impl RestartPolicy {
+    /// The number of conflicts after which to restart, once the search restarted `restarts` times.
+    fn conflicts_before_restart(&self, restarts: u64) -> u64 {
+        match *self {
+            Self::Luby { unit } => unit.max(1).saturating_mul(luby(restarts + 1)),
+        }
+    }
+}
+
+/// The `i`-th term of the Luby sequence, starting from 1.
+fn luby(mut i: u64) -> u64 {
+    loop {
+        // The smallest prefix `1, 1, 2, ..., 2^(k-1)` of length `2^k - 1` reaching `i`.
+        let mut k = 1;
+        while (1 << k) - 1 < i {
+            k += 1;
+        }
+        if (1 << k) - 1 == i {
+            return 1 << (k - 1);
+        }
+        // Otherwise the sequence repeats itself after its first `2^(k-1) - 1` terms.
+        i -= (1 << (k - 1)) - 1;
+    }
+}
+
+/// The limit of [ResolveOptions] that stopped a resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// [ResolveOptions::max_decisions]
+    Decisions,
+    /// [ResolveOptions::max_conflicts]
+    Conflicts,
+    /// [ResolveOptions::max_get_dependencies_calls]
+    GetDependenciesCalls,
+    /// [ResolveOptions::deadline]
+    Deadline,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decisions => write!(f, "the maximum number of decisions"),
+            Self::Conflicts => write!(f, "the maximum number of conflicts"),
+            Self::GetDependenciesCalls => {
+                write!(f, "the maximum number of calls to get_dependencies")
+            }
+            Self::Deadline => write!(f, "the deadline"),
+        }
+    }
+}
+
+/// The best solution found by [Solver::optimize] or [resolve_optimal].
+#[derive(Debug, Clone)]
+pub struct Optimum<P: Package, V> {
+    /// The selected packages and versions.
+    pub solution: Map<P, V>,
+    /// The total cost of the solution.
+    pub cost: u64,
+    /// Whether the search proved that there is no cheaper solution,
+    /// as opposed to running out of budget.
+    pub proven_optimal: bool,
+}
+
+/// A version newer than the selected one, from [Solver::why_not_newer].
+#[derive(Debug, Clone)]
+pub struct NewerVersion<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The newer version.
+    pub version: VS::V,
+    /// The proof that the resolution excluded that version, from a dependency constraint
+    /// or from incompatibilities learned during the resolution.
+    ///
+    /// It is [None] when nothing excluded that version,
+    /// and the dependency provider chose an older one, for example a preferred version.
+    pub reason: Option<DerivationTree<P, VS, M>>,
+}
+
+/// The solution of [resolve_upgrade], with the upgrades that locked packages prevented.
+#[derive(Debug, Clone)]
+pub struct Upgrade<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The selected packages and versions.
+    pub solution: Map<P, VS::V>,
+    /// The unlocked packages that could not move to the version the dependency provider prefers.
+    pub blocked: Vec<BlockedUpgrade<P, VS, M>>,
+}
+
+/// An unlocked package that locked packages kept from moving to a newer version.
+#[derive(Debug, Clone)]
+pub struct BlockedUpgrade<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The unlocked package.
+    pub package: P,
+    /// The version the dependency provider prefers for it.
+    pub version: VS::V,
+    /// The locked packages involved in the exclusion of that version,
+    /// which would need to be unlocked as well.
+    pub locked: Vec<P>,
+    /// Why that version is excluded.
+    pub reason: DerivationTree<P, VS, M>,
+}
+
+/// The solutions of [resolve_universal], one for each group of environments
+/// that resolve the same way.
+#[derive(Debug, Clone)]
+pub struct UniversalSolution<P: Package, V, E> {
+    /// Each group of environments, with its solution.
+    pub forks: Vec<(Vec<E>, Map<P, V>)>,
+}
+
+impl<P: Package, V: Clone + Eq, E: Clone + Eq> UniversalSolution<P, V, E> {
+    /// The solution for one of the environments.
+    pub fn solution(&self, environment: &E) -> Option<&Map<P, V>> {
+        self.forks
+            .iter()
+            .find(|(environments, _)| environments.contains(environment))
+            .map(|(_, solution)| solution)
+    }
+
+    /// For each package of any solution, its selected versions,
+    /// each with the environments it is selected in.
+    #[allow(clippy::type_complexity)]
+    pub fn lock(&self) -> Map<P, Vec<(V, Vec<E>)>> {
+        let mut lock = Map::<_, Vec<(V, Vec<E>)>>::default();
+        for (environments, solution) in &self.forks {
+            for (package, version) in solution {
+                let versions = lock.entry(package.clone()).or_default();
+                match versions.iter_mut().find(|(v, _)| v == version) {
+                    Some((_, selected_in)) => selected_in.extend(environments.iter().cloned()),
+                    None => versions.push((version.clone(), environments.clone())),
+                }
+            }
+        }
+        lock
+    }
+}
+
+/// A solution with the dependencies between its packages, from [resolve_with_graph].
+#[derive(Debug, Clone)]
+pub struct ResolutionGraph<P: Package, VS: VersionSet> {
+    /// The selected packages and versions.
+    pub solution: Map<P, VS::V>,
+    /// The dependencies between the selected packages.
+    pub edges: Vec<DependencyEdge<P, VS>>,
+}
+
+/// The dependency of a selected package version on another selected package,
+/// from [Solver::dependency_edges].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyEdge<P, VS> {
+    /// The package whose selected version has the dependency.
+    pub dependent: P,
+    /// The package it depends on.
+    pub dependency: P,
+    /// The versions of the dependency it requires.
+    pub range: VS,
+}
+
+/// A preferred version that a resolution had to move away from.
+#[derive(Debug, Clone)]
+pub struct LockChange<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The package whose version changed.
+    pub package: P,
+    /// The preferred version.
+    pub locked: VS::V,
+    /// The version in the solution, or [None] if the package is not needed anymore.
+    pub selected: Option<VS::V>,
+    /// Why the preferred version could not be kept, when it was excluded.
+    pub reason: Option<DerivationTree<P, VS, M>>,
+}
+
+/// Incompatibilities learned by a resolution, from [Solver::learned_incompatibilities],
+/// to give a head start to other resolutions with [Solver::with_learned_incompatibilities].
+///
+/// They are derived only from what the dependency provider told about packages,
+/// never from the root package, requirements, exclusions, locks or excluded solutions
+/// of the resolution that learned them.
+/// They hold for any resolution whose dependency provider gives the same answers,
+/// like another root resolved against the same registry snapshot.
+/// Each one keeps the incompatibilities it is derived from,
+/// so that they can still be explained in a [DerivationTree].
+#[derive(Debug, Clone)]
+pub struct LearnedIncompatibilities<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
+    /// The learned incompatibilities and their causes, each cause before what is derived from it.
+    pub(crate) incompatibility_store: Arena<Incompatibility<P, VS, M>>,
+    /// The packages of the incompatibilities in the store.
+    pub(crate) package_store: HashArena<P>,
+    /// The learned incompatibilities, to add to a resolution.
+    pub(crate) learned: Vec<IncompId<P, VS, M>>,
+}
+
+impl<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display>
+    LearnedIncompatibilities<P, VS, M>
+{
+    /// The number of learned incompatibilities, not counting their causes.
+    pub fn len(&self) -> usize {
+        self.learned.len()
+    }
+
+    /// Whether nothing was learned.
+    pub fn is_empty(&self) -> bool {
+        self.learned.is_empty()
+    }
+}
+
 /// An enum used by [DependencyProvider] that holds information about package dependencies.
 /// For each [Package] there is a set of versions allowed as a dependency.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum Dependencies<P: Package, VS: VersionSet, M: Eq + Clone + Debug + Display> {
     /// Package dependencies are unavailable with the reason why they are missing.
     Unavailable(M),
     /// Container for all available package versions.
     Available(DependencyConstraints<P, VS>),
+    /// Package dependencies that are available, and may contain other kinds of dependencies
+    /// than requirements, see [Dependency].
+    Extended(Vec<Dependency<P, VS>>),
+}
+
+/// A single dependency of a package version, used by [Dependencies::Extended].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dependency<P: Package, VS: VersionSet> {
+    /// A version of the package in the given set must be selected.
+    Requires(P, VS),
+    /// If the package is selected, its version must be in the given set.
+    ///
+    /// This does not require the package: a package conflicting with `b < 2`
+    /// is expressed as constraining `b` to `>= 2`.
+    Constrains(P, VS),
+    /// A version of at least one of the packages, in its given set, must be selected.
+    ///
+    /// Alternatives are tried in the given order when none of them is selected yet.
+    AnyOf(Vec<(P, VS)>),
+    /// This package version provides the given version of a virtual package,
+    /// so selecting it satisfies the requirements on that version.
+    ///
+    /// Once a provider is decided on, the virtual package is decided on the version it provides
+    /// without asking the [DependencyProvider], and it is left out of the
+    /// [solution](Solver::solution), where its provider stands in for it.
+    /// [Solver::providers] tells which package version provides each virtual package.
+    /// A virtual package has a single version in a solution,
+    /// so providers of different versions of it cannot be selected together.
+    ///
+    /// A virtual package needed before any of its providers is selected is asked for
+    /// like any other package: its versions can depend on [any of](Dependency::AnyOf)
+    /// the packages that provide them.
+    Provides(P, VS::V),
+}
+
+/// The types a resolution works with, as given by its dependency provider.
+///
+/// Every [DependencyProvider] has them, see there for what each of them is.
+/// An [AsyncDependencyProvider] declares them by implementing this trait.
+pub trait ProviderTypes {
+    /// How this provider stores the name of the packages, see [DependencyProvider::P].
+    type P: Package;
+
+    /// How this provider stores the versions of the packages, see [DependencyProvider::V].
+    type V: Debug + Display + Clone + Ord;
+
+    /// How this provider stores the version requirements for the packages,
+    /// see [DependencyProvider::VS].
+    type VS: VersionSet<V = Self::V>;
+
+    /// Type for custom incompatibilities, see [DependencyProvider::M].
+    type M: Eq + Clone + Debug + Display;
+
+    /// The type returned when prioritizing packages, see [DependencyProvider::Priority].
+    type Priority: Ord + Clone;
+
+    /// The kind of error returned by this provider, see [DependencyProvider::Err].
+    type Err: Error + 'static;
+}
+
+impl<DP: DependencyProvider> ProviderTypes for DP {
+    type P = DP::P;
+    type V = DP::V;
+    type VS = DP::VS;
+    type M = DP::M;
+    type Priority = DP::Priority;
+    type Err = DP::Err;
 }
 
 /// Trait that allows the algorithm to retrieve available packages and their dependencies.
@@ -238,9 +2057,19 @@ pub trait DependencyProvider {
     /// > since these packages will run out of versions to try more quickly.
     /// > But there's likely room for improvement in these heuristics.
     ///
+    /// The `package_statistics` tell how often the package was involved in conflicts so far.
+    /// Deciding early on packages that keep causing conflicts
+    /// can avoid making many decisions that the same conflicts undo again and again.
+    ///
     /// Note: the resolver may call this even when the range has not changed,
     /// if it is more efficient for the resolvers internal data structures.
-    fn prioritize(&self, package: &Self::P, range: &Self::VS) -> Self::Priority;
+    /// It calls it again when the statistics of a package change.
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority;
     /// The type returned from `prioritize`. The resolver does not care what type this is
     /// as long as it can pick a largest one and clone it.
     ///
@@ -279,6 +2108,85 @@ pub trait DependencyProvider {
     fn should_cancel(&self) -> Result<(), Self::Err> {
         Ok(())
     }
+
+    /// Called with the dependencies of a version that introduce packages
+    /// the resolver did not know about yet, right after retrieving them.
+    ///
+    /// The resolver is likely to call [choose_version](DependencyProvider::choose_version)
+    /// and [get_dependencies](DependencyProvider::get_dependencies) for those packages soon,
+    /// so this is a good place to start fetching their metadata in the background.
+    /// If not provided nothing is prefetched.
+    fn prefetch(&self, _dependencies: &[(Self::P, Self::VS)]) {}
+}
+
+/// A [DependencyProvider] whose dependencies depend on the environment,
+/// like the operating system or the interpreter version,
+/// to resolve for several environments at once with [resolve_universal].
+pub trait EnvironmentDependencyProvider: DependencyProvider {
+    /// An environment to resolve the dependencies for.
+    type Environment: Clone + Eq + Debug;
+
+    /// Retrieves the dependencies of a package version in one environment,
+    /// keeping only the dependencies whose environment predicate matches it.
+    ///
+    /// Universal resolution calls this instead of
+    /// [get_dependencies](DependencyProvider::get_dependencies).
+    #[allow(clippy::type_complexity)]
+    fn get_environment_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+        environment: &Self::Environment,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err>;
+}
+
+/// Trait that allows the algorithm to retrieve available packages and their dependencies
+/// asynchronously, for example from a remote registry.
+///
+/// It mirrors [DependencyProvider], except that retrieving versions and dependencies
+/// returns futures, which [resolve_async] awaits.
+/// Its types are given by implementing [ProviderTypes].
+pub trait AsyncDependencyProvider: ProviderTypes {
+    /// Prioritize the packages to decide on next, see [DependencyProvider::prioritize].
+    ///
+    /// This is not async as it is called for every potential package,
+    /// and should only rely on what is already known.
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority;
+
+    /// Choose the version of a package to use among the given range,
+    /// see [DependencyProvider::choose_version].
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> impl Future<Output = Result<Option<Self::V>, Self::Err>>;
+
+    /// Retrieves the package dependencies.
+    /// Return [Dependencies::Unavailable] if its dependencies are unavailable.
+    #[allow(clippy::type_complexity)]
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> impl Future<Output = Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err>>;
+
+    /// Called regularly during the resolution to terminate it early,
+    /// see [DependencyProvider::should_cancel].
+    fn should_cancel(&self) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    /// Called with the dependencies introducing new packages,
+    /// see [DependencyProvider::prefetch].
+    ///
+    /// This is not async: the provider is expected to start fetching in the background,
+    /// for example by spawning tasks, rather than waiting for the metadata to arrive.
+    fn prefetch(&self, _dependencies: &[(Self::P, Self::VS)]) {}
 }
 
 /// A basic implementation of [DependencyProvider].
@@ -369,7 +2277,12 @@ impl<P: Package, VS: VersionSet> DependencyProvider for OfflineDependencyProvide
     }
 
     type Priority = Reverse<usize>;
-    fn prioritize(&self, package: &P, range: &VS) -> Self::Priority {
+    fn prioritize(
+        &self,
+        package: &P,
+        range: &VS,
+        _package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
         Reverse(
             self.dependencies
                 .get(package)
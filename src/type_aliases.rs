@@ -2,7 +2,7 @@
 
 //! Publicly exported type aliases.
 
-use crate::DependencyProvider;
+use crate::ProviderTypes;
 
 /// Map implementation used by the library.
 pub type Map<K, V> = rustc_hash::FxHashMap<K, V>;
@@ -12,12 +12,11 @@ pub type Set<V> = rustc_hash::FxHashSet<V>;
 
 /// Concrete dependencies picked by the library during [resolve](crate::solver::resolve)
 /// from [DependencyConstraints].
-pub type SelectedDependencies<DP> =
-    Map<<DP as DependencyProvider>::P, <DP as DependencyProvider>::V>;
+pub type SelectedDependencies<DP> = Map<<DP as ProviderTypes>::P, <DP as ProviderTypes>::V>;
 
 /// Holds information about all possible versions a given package can accept.
 /// There is a difference in semantics between an empty map
 /// inside [DependencyConstraints] and [Dependencies::Unavailable](crate::solver::Dependencies::Unavailable):
 /// the former means the package has no dependency and it is a known fact,
-/// while the latter means they could not be fetched by the [DependencyProvider].
+/// while the latter means they could not be fetched by the [DependencyProvider](crate::DependencyProvider).
 pub type DependencyConstraints<P, VS> = Map<P, VS>;
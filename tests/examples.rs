@@ -1,8 +1,8 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use pubgrub::{
-    resolve, DefaultStringReporter, Map, OfflineDependencyProvider, PubGrubError, Ranges,
-    Reporter as _, SemanticVersion, Set,
+    resolve, resolve_requirements, DefaultStringReporter, Map, OfflineDependencyProvider,
+    PubGrubError, Ranges, Reporter as _, SemanticVersion, Set,
 };
 
 type NumVS = Ranges<u32>;
@@ -251,3 +251,59 @@ And because there is no version of foo in <1 | >1, <2 | >2, <3 | >3, <4 | >4, <5
         Set::from_iter(&["root", "foo", "bar"])
     );
 }
+
+#[test]
+fn requirements_without_root_package() {
+    init_log();
+    let mut dependency_provider = OfflineDependencyProvider::<&str, NumVS>::new();
+    dependency_provider.add_dependencies("foo", 1u32, vec![("bar", Ranges::full())]);
+    dependency_provider.add_dependencies("foo", 2u32, vec![("bar", Ranges::higher_than(2u32))]);
+    dependency_provider.add_dependencies("bar", 1u32, vec![]);
+    dependency_provider.add_dependencies("bar", 2u32, vec![]);
+    dependency_provider.add_dependencies("baz", 1u32, vec![]);
+
+    let requirements = Map::from_iter([("foo", Ranges::full()), ("baz", Ranges::full())]);
+    let exclusions = Map::from_iter([("bar", Ranges::singleton(2u32))]);
+
+    // Solution.
+    let mut expected_solution = Map::default();
+    expected_solution.insert("foo", 1u32);
+    expected_solution.insert("bar", 1u32);
+    expected_solution.insert("baz", 1u32);
+
+    // Run the algorithm.
+    let computed_solution =
+        resolve_requirements(&dependency_provider, requirements, exclusions).unwrap();
+    assert_eq!(expected_solution, computed_solution);
+}
+
+#[test]
+fn requirements_report_without_root_package() {
+    let mut dependency_provider = OfflineDependencyProvider::<&str, NumVS>::new();
+    for i in 1..6 {
+        // foo depends on bar...
+        dependency_provider.add_dependencies("foo", i as u32, vec![("bar", Ranges::full())]);
+    }
+    dependency_provider.add_dependencies("bar", 1u32, vec![]);
+    dependency_provider.add_dependencies("baz", 1u32, vec![]);
+
+    let requirements = Map::from_iter([("foo", Ranges::full()), ("baz", Ranges::full())]);
+    let exclusions = Map::from_iter([("bar", Ranges::full())]);
+
+    let Err(PubGrubError::NoSolution(mut derivation_tree)) =
+        resolve_requirements(&dependency_provider, requirements, exclusions)
+    else {
+        unreachable!()
+    };
+    derivation_tree.collapse_no_versions();
+    assert_eq!(
+        &DefaultStringReporter::report(&derivation_tree),
+        "Because foo depends on bar and your requirements include foo, bar * is mandatory.
+And because your requirements exclude bar, version solving failed."
+    );
+    assert_eq!(
+        derivation_tree.packages(),
+        // baz isn't shown.
+        Set::from_iter(&["foo", "bar"])
+    );
+}
@@ -12,9 +12,10 @@ use proptest::sample::Index;
 use proptest::string::string_regex;
 
 use pubgrub::{
-    resolve, DefaultStringReporter, Dependencies, DependencyProvider, DerivationTree, External,
-    OfflineDependencyProvider, Package, PubGrubError, Ranges, Reporter, SelectedDependencies,
-    VersionSet,
+    resolve, resolve_all, resolve_requirements, resolve_with_options, DefaultStringReporter,
+    Dependencies, DependencyProvider, DerivationTree, External, Map, OfflineDependencyProvider,
+    Package, PackageResolutionStatistics, PubGrubError, Ranges, Reporter, ResolveOptions,
+    RestartPolicy, SelectedDependencies, Solver, VersionSet,
 };
 
 use crate::sat_dependency_provider::SatResolve;
@@ -49,8 +50,13 @@ impl<P: Package, VS: VersionSet> DependencyProvider for OldestVersionsDependency
 
     type Priority = <OfflineDependencyProvider<P, VS> as DependencyProvider>::Priority;
 
-    fn prioritize(&self, package: &P, range: &VS) -> Self::Priority {
-        self.0.prioritize(package, range)
+    fn prioritize(
+        &self,
+        package: &P,
+        range: &VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.0.prioritize(package, range, package_statistics)
     }
 
     type Err = Infallible;
@@ -104,8 +110,13 @@ impl<DP: DependencyProvider> DependencyProvider for TimeoutDependencyProvider<DP
 
     type Priority = DP::Priority;
 
-    fn prioritize(&self, package: &DP::P, range: &DP::VS) -> Self::Priority {
-        self.dp.prioritize(package, range)
+    fn prioritize(
+        &self,
+        package: &DP::P,
+        range: &DP::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.dp.prioritize(package, range, package_statistics)
     }
 
     type Err = DP::Err;
@@ -316,7 +327,7 @@ fn retain_versions<N: Package + Ord, VS: VersionSet>(
                 continue;
             }
             let deps = match dependency_provider.get_dependencies(n, v).unwrap() {
-                Dependencies::Unavailable(_) => panic!(),
+                Dependencies::Unavailable(_) | Dependencies::Extended(_) => panic!(),
                 Dependencies::Available(deps) => deps,
             };
             smaller_dependency_provider.add_dependencies(n.clone(), v.clone(), deps)
@@ -340,7 +351,7 @@ fn retain_dependencies<N: Package + Ord, VS: VersionSet>(
     for n in dependency_provider.packages() {
         for v in dependency_provider.versions(n).unwrap() {
             let deps = match dependency_provider.get_dependencies(n, v).unwrap() {
-                Dependencies::Unavailable(_) => panic!(),
+                Dependencies::Unavailable(_) | Dependencies::Extended(_) => panic!(),
                 Dependencies::Available(deps) => deps,
             };
             smaller_dependency_provider.add_dependencies(
@@ -446,6 +457,67 @@ proptest! {
         }
     }
 
+    #[test]
+    /// Restarting the search after every conflict changes which solution is found
+    /// but not the existence of a solution.
+    fn prop_restarts_errors_the_same(
+        (dependency_provider, cases) in registry_strategy(0u16..665)
+    )  {
+        for (name, ver) in cases {
+            let options = ResolveOptions {
+                restarts: Some(RestartPolicy::Luby { unit: 1 }),
+                ..ResolveOptions::default()
+            };
+            let l = timeout_resolve(dependency_provider.clone(), name, ver);
+            let r = resolve_with_options(
+                &TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000),
+                name,
+                ver,
+                options,
+            );
+            match (&l, &r) {
+                (Ok(_), Ok(_)) => (),
+                (Err(PubGrubError::NoSolution(_)), Err(PubGrubError::NoSolution(_))) => (),
+                _ => panic!("not the same result")
+            }
+        }
+    }
+
+    #[test]
+    /// Incompatibilities learned from other roots do not change the existence of a solution,
+    /// and the solutions found with them are still valid.
+    fn prop_learned_incompatibilities_errors_the_same(
+        (dependency_provider, cases) in registry_strategy(0u16..665)
+    )  {
+        let timeout_provider = TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000);
+        let mut learned = None;
+        for (name, ver) in cases {
+            let l = timeout_resolve(dependency_provider.clone(), name, ver);
+            let mut solver = Solver::new(name, ver);
+            if let Some(learned) = &learned {
+                solver = solver.with_learned_incompatibilities(learned);
+            }
+            let r = solver.solve(&timeout_provider);
+            match (&l, &r) {
+                (Ok(_), Ok(solution)) => {
+                    for (package, version) in solution {
+                        let Dependencies::Available(dependencies) =
+                            dependency_provider.get_dependencies(package, version).unwrap()
+                        else {
+                            panic!("no dependencies for {package} {version}");
+                        };
+                        for (dependency, range) in dependencies {
+                            prop_assert!(solution.get(&dependency).is_some_and(|v| range.contains(v)));
+                        }
+                    }
+                }
+                (Err(PubGrubError::NoSolution(_)), Err(PubGrubError::NoSolution(_))) => (),
+                _ => panic!("not the same result")
+            }
+            learned = Some(solver.learned_incompatibilities());
+        }
+    }
+
     #[test]
     fn prop_errors_the_same_with_only_report_dependencies(
         (dependency_provider, cases) in registry_strategy(0u16..665)
@@ -494,6 +566,97 @@ proptest! {
         }
     }
 
+    #[test]
+    /// Solving a single requirement on an exact version without a root package
+    /// finds a solution if and only if solving the dependencies of that version does.
+    fn prop_requirements_errors_the_same(
+        (dependency_provider, cases) in registry_strategy(0u16..665)
+    )  {
+        for (name, ver) in cases {
+            let rooted = timeout_resolve(dependency_provider.clone(), name, ver);
+            let requirements = Map::from_iter([(name, Ranges::singleton(ver))]);
+            let rootless = resolve_requirements(
+                &TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000),
+                requirements,
+                Map::default(),
+            );
+            match (&rooted, &rootless) {
+                (Ok(_), Ok(_)) => (),
+                (Err(PubGrubError::NoSolution(_)), Err(PubGrubError::NoSolution(_))) => (),
+                _ => panic!("not the same result")
+            }
+        }
+    }
+
+    #[test]
+    /// The first solution found when enumerating is the one [resolve] finds,
+    /// and the following ones are distinct and valid.
+    fn prop_enumerated_solutions_are_distinct_and_valid(
+        (dependency_provider, cases) in registry_strategy(0u16..665)
+    )  {
+        for (name, ver) in cases {
+            let timeout_provider = TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000);
+            let solutions: Vec<_> = resolve_all(&timeout_provider, name, ver).take(5).collect();
+            match (timeout_resolve(dependency_provider.clone(), name, ver), &solutions[0]) {
+                (Ok(l), Ok(r)) => prop_assert_eq!(&l, r),
+                (Err(PubGrubError::NoSolution(_)), Err(PubGrubError::NoSolution(_))) => {
+                    prop_assert_eq!(solutions.len(), 1)
+                }
+                _ => panic!("not the same result")
+            }
+            for (i, solution) in solutions.iter().enumerate() {
+                let Ok(solution) = solution else { continue };
+                prop_assert_eq!(solution.get(&name), Some(&ver));
+                for (p, v) in solution {
+                    let Dependencies::Available(deps) = dependency_provider.get_dependencies(p, v).unwrap() else {
+                        panic!("{p} {v} has unavailable dependencies in a solution")
+                    };
+                    for (dep, range) in deps {
+                        prop_assert!(solution.get(&dep).is_some_and(|v| range.contains(v)));
+                    }
+                }
+                for other in &solutions[..i] {
+                    prop_assert_ne!(other.as_ref().ok(), Some(solution));
+                }
+            }
+        }
+    }
+
+    #[test]
+    /// The optimal solution is never more expensive than the first solution found,
+    /// and once proven optimal, it is as cheap as the cheapest one when they can all be enumerated.
+    fn prop_optimal_solution_is_the_cheapest(
+        (dependency_provider, cases) in registry_strategy(0u16..665)
+    )  {
+        let cost = |p: &u16, v: &u32| u64::from(*p % 3) + u64::from(*v);
+        for (name, ver) in cases {
+            let timeout_provider = TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000);
+            let solutions: Vec<_> = resolve_all(&timeout_provider, name, ver)
+                .take(30)
+                .filter_map(Result::ok)
+                .collect();
+            let timeout_provider = TimeoutDependencyProvider::new(dependency_provider.clone(), 50_000);
+            let Ok(optimum) = Solver::new(name, ver).optimize(&timeout_provider, cost, Some(100)) else {
+                prop_assert!(solutions.is_empty());
+                continue;
+            };
+            prop_assert_eq!(
+                optimum.cost,
+                optimum.solution.iter().map(|(p, v)| cost(p, v)).sum::<u64>()
+            );
+            let costs = solutions
+                .iter()
+                .map(|solution| solution.iter().map(|(p, v)| cost(p, v)).sum::<u64>());
+            prop_assert!(optimum.cost <= costs.clone().next().unwrap());
+            if optimum.proven_optimal {
+                prop_assert!(costs.clone().all(|solution_cost| optimum.cost <= solution_cost));
+                if solutions.len() < 30 {
+                    prop_assert_eq!(Some(optimum.cost), costs.min());
+                }
+            }
+        }
+    }
+
     #[test]
     fn prop_removing_a_dep_cant_break(
         (dependency_provider, cases) in registry_strategy(0u16..665),
@@ -511,7 +674,7 @@ proptest! {
                 .get_dependencies(package, version)
                 .unwrap()
             {
-                Dependencies::Unavailable(_) => panic!(),
+                Dependencies::Unavailable(_) | Dependencies::Extended(_) => panic!(),
                 Dependencies::Available(d) => d.into_iter().collect(),
             };
             if !dependencies.is_empty() {
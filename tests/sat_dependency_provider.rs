@@ -65,7 +65,7 @@ impl<P: Package, VS: VersionSet> SatResolve<P, VS> {
         // active packages need each of there `deps` to be satisfied
         for (p, v, var) in &all_versions {
             let deps = match dp.get_dependencies(p, v).unwrap() {
-                Dependencies::Unavailable(_) => panic!(),
+                Dependencies::Unavailable(_) | Dependencies::Extended(_) => panic!(),
                 Dependencies::Available(d) => d,
             };
             for (p1, range) in &deps {
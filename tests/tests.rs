@@ -1,6 +1,21 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use pubgrub::{resolve, OfflineDependencyProvider, PubGrubError, Ranges};
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use pubgrub::{
+    resolve, resolve_all, resolve_async, resolve_optimal, resolve_universal, resolve_upgrade,
+    resolve_with_graph, resolve_with_options, resolve_with_stats, AsyncDependencyProvider,
+    Bucketed, Buckets, DefaultStringReporter, Dependencies, Dependency, DependencyEdge,
+    DependencyProvider, DerivationTree, EnvironmentDependencyProvider, External,
+    IncompatibilityTerms, Limit, Map, Observer, OfflineDependencyProvider,
+    PackageResolutionStatistics, PartialResolution, ProviderTypes, PubGrubError, Ranges, Reporter,
+    ResolveOptions, RestartPolicy, SelectionStep, Solver, Term, Upgrade,
+};
 
 type NumVS = Ranges<u32>;
 
@@ -50,3 +65,1397 @@ fn depend_on_self() {
     dependency_provider.add_dependencies("a", 66u32, [("a", Ranges::singleton(111u32))]);
     assert!(resolve(&dependency_provider, "a", 66u32).is_err());
 }
+
+#[test]
+fn manual_solver_loop_matches_resolve() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("a", 0u32, [("b", Ranges::full()), ("c", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 0u32, []);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::between(0u32, 1u32))]);
+    dependency_provider.add_dependencies("c", 0u32, []);
+    dependency_provider.add_dependencies("c", 2u32, []);
+
+    let mut solver = Solver::<OfflineDependencyProvider<_, NumVS>>::new("a", 0u32);
+    let solution = loop {
+        solver.propagate().unwrap();
+        let Some(package) = solver.pick_package(|p, r, s| dependency_provider.prioritize(p, r, s))
+        else {
+            break solver.solution();
+        };
+        let Some(Term::Positive(range)) = solver.term_intersection_for_package(&package) else {
+            panic!("picked packages have a positive term");
+        };
+        let range = range.clone();
+        let Some(version) = dependency_provider
+            .choose_version(&package, &range)
+            .unwrap()
+        else {
+            solver.add_no_versions(package, range);
+            continue;
+        };
+        if solver.has_dependencies(&package, &version) {
+            solver.add_decision(package, version).unwrap();
+            continue;
+        }
+        match dependency_provider
+            .get_dependencies(&package, &version)
+            .unwrap()
+        {
+            Dependencies::Unavailable(reason) => solver.add_unavailable(package, version, reason),
+            Dependencies::Available(deps) => solver.add_dependencies(package, version, deps),
+            Dependencies::Extended(deps) => {
+                solver.add_extended_dependencies(package, version, deps)
+            }
+        }
+        assert!(solver.decisions().count() <= 3);
+    };
+
+    assert_eq!(solution, resolve(&dependency_provider, "a", 0u32).unwrap());
+}
+
+#[test]
+fn add_decision_rejects_invalid_versions() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("a", 0u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 0u32, []);
+
+    let mut solver = Solver::new("a", 0u32);
+    solver.solve(&dependency_provider).unwrap();
+    // a is already decided, and the dependencies of b 1 were never added.
+    assert!(matches!(
+        solver.add_decision("a", 0u32),
+        Err(PubGrubError::InvalidDecision { .. })
+    ));
+    assert!(matches!(
+        solver.add_decision("b", 1u32),
+        Err(PubGrubError::InvalidDecision { .. })
+    ));
+    assert_eq!(
+        solver.solution(),
+        resolve(&dependency_provider, "a", 0u32).unwrap()
+    );
+}
+
+#[test]
+fn preferences_keep_locked_versions() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, [("b", Ranges::singleton(1u32))]);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::singleton(2u32))]);
+    dependency_provider.add_dependencies("b", 1u32, []);
+    dependency_provider.add_dependencies("b", 2u32, []);
+    dependency_provider.add_dependencies("b", 3u32, []);
+    let lock: Map<_, _> = [("root", 0u32), ("a", 1u32), ("b", 1u32), ("c", 1u32)]
+        .into_iter()
+        .collect();
+
+    // Nothing changed, so the locked versions are kept even though newer ones exist.
+    let mut solver = Solver::<OfflineDependencyProvider<_, NumVS>>::new("root", 0u32)
+        .with_preferences(lock.clone());
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+    assert_eq!(solution.get("b"), Some(&1));
+    let changes = solver.lock_changes();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].package, "c");
+    assert_eq!(changes[0].selected, None);
+    assert!(changes[0].reason.is_none());
+
+    // The manifest now requires a newer b, which forces a to move as well.
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("b", Ranges::higher_than(2u32))],
+    );
+    let mut solver =
+        Solver::<OfflineDependencyProvider<_, NumVS>>::new("root", 0u32).with_preferences(lock);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("a"), Some(&2));
+    assert_eq!(solution.get("b"), Some(&2));
+    let changes: Map<_, _> = solver
+        .lock_changes()
+        .into_iter()
+        .map(|change| (change.package, change))
+        .collect();
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes["a"].selected, Some(2));
+    assert!(matches!(
+        changes["a"].reason,
+        Some(DerivationTree::External(External::FromDependencyOf(
+            "a",
+            _,
+            "b",
+            _
+        )))
+    ));
+    assert_eq!(changes["b"].selected, Some(2));
+    assert!(matches!(
+        changes["b"].reason,
+        Some(DerivationTree::External(External::FromDependencyOf(
+            "root",
+            _,
+            "b",
+            _
+        )))
+    ));
+}
+
+#[test]
+fn enumerate_all_solutions() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, [("b", Ranges::singleton(1u32))]);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, []);
+    dependency_provider.add_dependencies("b", 2u32, []);
+
+    let solutions: Vec<_> = resolve_all(&dependency_provider, "root", 0u32)
+        .map(|solution| {
+            let solution = solution.unwrap();
+            (solution["a"], solution["b"])
+        })
+        .collect();
+    assert_eq!(solutions, vec![(2, 2), (2, 1), (1, 1)]);
+
+    let first = resolve_all(&dependency_provider, "root", 0u32).next();
+    assert_eq!(
+        first.unwrap().unwrap(),
+        resolve(&dependency_provider, "root", 0u32).unwrap()
+    );
+
+    dependency_provider.add_dependencies("a", 3u32, [("b", Ranges::singleton(3u32))]);
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::singleton(3u32))]);
+    let mut solutions = resolve_all(&dependency_provider, "root", 0u32);
+    assert!(matches!(
+        solutions.next(),
+        Some(Err(PubGrubError::NoSolution(_)))
+    ));
+    assert!(solutions.next().is_none());
+}
+
+#[test]
+fn optimal_solution_with_fewest_packages() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::full())]);
+    dependency_provider.add_dependencies("c", 1u32, []);
+
+    // The newest version of a is picked first, which pulls in b and c.
+    assert_eq!(
+        resolve(&dependency_provider, "root", 0u32).unwrap().len(),
+        4
+    );
+
+    let optimum = resolve_optimal(&dependency_provider, "root", 0u32, |_, _| 1).unwrap();
+    assert!(optimum.proven_optimal);
+    assert_eq!(optimum.cost, 2);
+    assert_eq!(optimum.solution.get("a"), Some(&1));
+
+    // Preferring newer versions gives back the solution of resolve.
+    let optimum = resolve_optimal(&dependency_provider, "root", 0u32, |p, v| {
+        dependency_provider
+            .versions(p)
+            .unwrap()
+            .filter(|newer| *newer > v)
+            .count() as u64
+    })
+    .unwrap();
+    assert!(optimum.proven_optimal);
+    assert_eq!(optimum.cost, 0);
+    assert_eq!(
+        optimum.solution,
+        resolve(&dependency_provider, "root", 0u32).unwrap()
+    );
+}
+
+/// An [AsyncDependencyProvider] whose futures are pending once before completing.
+struct YieldingDependencyProvider(OfflineDependencyProvider<&'static str, NumVS>);
+
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl ProviderTypes for YieldingDependencyProvider {
+    type P = &'static str;
+    type V = u32;
+    type VS = NumVS;
+    type M = String;
+    type Priority =
+        <OfflineDependencyProvider<&'static str, NumVS> as DependencyProvider>::Priority;
+    type Err = Infallible;
+}
+
+impl AsyncDependencyProvider for YieldingDependencyProvider {
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.0.prioritize(package, range, package_statistics)
+    }
+
+    async fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        YieldOnce(false).await;
+        self.0.choose_version(package, range)
+    }
+
+    async fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        YieldOnce(false).await;
+        self.0.get_dependencies(package, version)
+    }
+}
+
+#[test]
+fn async_provider_matches_resolve() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("a", 0u32, [("b", Ranges::full()), ("c", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 0u32, []);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::between(0u32, 1u32))]);
+    dependency_provider.add_dependencies("c", 0u32, []);
+    dependency_provider.add_dependencies("c", 2u32, []);
+    let expected = resolve(&dependency_provider, "a", 0u32).unwrap();
+
+    let async_provider = YieldingDependencyProvider(dependency_provider);
+    let solution = pollster::block_on(resolve_async(&async_provider, "a", 0u32)).unwrap();
+    assert_eq!(solution, expected);
+}
+
+/// Records the packages that are prefetched and those a version is chosen for.
+struct PrefetchRecordingProvider {
+    dp: OfflineDependencyProvider<&'static str, NumVS>,
+    events: RefCell<Vec<(&'static str, &'static str)>>,
+}
+
+impl DependencyProvider for PrefetchRecordingProvider {
+    type P = &'static str;
+    type V = u32;
+    type VS = NumVS;
+    type M = String;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.dp.prioritize(package, range, package_statistics)
+    }
+    type Priority =
+        <OfflineDependencyProvider<&'static str, NumVS> as DependencyProvider>::Priority;
+
+    type Err = Infallible;
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.events.borrow_mut().push(("choose", package));
+        self.dp.choose_version(package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        self.dp.get_dependencies(package, version)
+    }
+
+    fn prefetch(&self, dependencies: &[(Self::P, Self::VS)]) {
+        for (package, _) in dependencies {
+            self.events.borrow_mut().push(("prefetch", package));
+        }
+    }
+}
+
+#[test]
+fn prefetch_new_packages_once_before_choosing_them() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("a", Ranges::full()), ("b", Ranges::full())]);
+    dp.add_dependencies("a", 1u32, [("b", Ranges::full()), ("c", Ranges::full())]);
+    dp.add_dependencies("b", 1u32, [("c", Ranges::full())]);
+    dp.add_dependencies("c", 1u32, []);
+    let provider = PrefetchRecordingProvider {
+        dp,
+        events: RefCell::default(),
+    };
+    resolve(&provider, "root", 0u32).unwrap();
+
+    let events = provider.events.into_inner();
+    let prefetched: Vec<_> = events
+        .iter()
+        .filter(|(event, _)| *event == "prefetch")
+        .map(|(_, package)| *package)
+        .collect();
+    assert_eq!(prefetched.len(), 3);
+    for package in ["a", "b", "c"] {
+        assert!(prefetched.contains(&package));
+        let prefetch = events.iter().position(|e| *e == ("prefetch", package));
+        let choose = events.iter().position(|e| *e == ("choose", package));
+        assert!(prefetch < choose);
+    }
+}
+
+#[test]
+fn prefetch_packages_only_known_from_learned_incompatibilities() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dp.add_dependencies("a", 1u32, []);
+    dp.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dp.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+    let mut solver = Solver::new("root", 0u32);
+    solver.solve(&dp).unwrap();
+    let learned = solver.learned_incompatibilities();
+    assert!(!learned.is_empty());
+
+    let provider = PrefetchRecordingProvider {
+        dp,
+        events: RefCell::default(),
+    };
+    Solver::new("root", 0u32)
+        .with_learned_incompatibilities(&learned)
+        .solve(&provider)
+        .unwrap();
+    let events = provider.events.into_inner();
+    assert!(events.contains(&("prefetch", "a")));
+}
+
+#[test]
+fn stats_on_success_and_failure() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+
+    // Deciding on root, a 2 and b 1 conflicts, so a 1 is decided on instead.
+    let (result, stats) = resolve_with_stats(&dependency_provider, "root", 0u32);
+    let solution = result.unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+    assert_eq!(stats.get_dependencies_calls, 4);
+    assert_eq!(stats.decisions, 4);
+    assert!(stats.conflicts >= 1);
+    assert!(stats.backtracks >= 1);
+    assert!(stats.learned_incompatibilities >= 1);
+    assert!(stats.unit_propagations >= solution.len() as u64);
+    assert!(stats.solver_time > Duration::ZERO);
+
+    // Time is only measured when asked for.
+    let mut solver = Solver::new("root", 0u32);
+    solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solver.stats().solver_time, Duration::ZERO);
+    assert_eq!(solver.stats().provider_time, Duration::ZERO);
+
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::singleton(2u32))]);
+    let (result, stats) = resolve_with_stats(&dependency_provider, "root", 0u32);
+    assert!(matches!(result, Err(PubGrubError::NoSolution(_))));
+    assert!(stats.conflicts >= 1);
+    assert!(stats.get_dependencies_calls >= 3);
+}
+
+#[test]
+fn resolution_stops_at_limits() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+
+    let options = ResolveOptions {
+        max_decisions: Some(2),
+        ..ResolveOptions::default()
+    };
+    match resolve_with_options(&dependency_provider, "root", 0u32, options) {
+        Err(PubGrubError::LimitReached { limit, stats }) => {
+            assert_eq!(limit, Limit::Decisions);
+            assert_eq!(stats.decisions, 2);
+        }
+        other => panic!("expected the decision limit, got {other:?}"),
+    }
+
+    let options = ResolveOptions {
+        max_conflicts: Some(1),
+        ..ResolveOptions::default()
+    };
+    match resolve_with_options(&dependency_provider, "root", 0u32, options) {
+        Err(PubGrubError::LimitReached { limit, stats }) => {
+            assert_eq!(limit, Limit::Conflicts);
+            assert_eq!(stats.conflicts, 2);
+        }
+        other => panic!("expected the conflict limit, got {other:?}"),
+    }
+
+    let options = ResolveOptions {
+        deadline: Some(Instant::now()),
+        ..ResolveOptions::default()
+    };
+    let result = resolve_with_options(&dependency_provider, "root", 0u32, options);
+    assert!(matches!(
+        result,
+        Err(PubGrubError::LimitReached {
+            limit: Limit::Deadline,
+            ..
+        })
+    ));
+
+    let options = ResolveOptions {
+        max_decisions: Some(4),
+        max_conflicts: Some(2),
+        max_get_dependencies_calls: Some(4),
+        deadline: Some(Instant::now() + Duration::from_secs(60)),
+        ..ResolveOptions::default()
+    };
+    let solution = resolve_with_options(&dependency_provider, "root", 0u32, options).unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+}
+
+#[test]
+fn restart_after_conflicts() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("x", Ranges::full()), ("a", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("x", 1u32, []);
+    dependency_provider.add_dependencies("x", 2u32, [("c", Ranges::singleton(1u32))]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::singleton(2u32))]);
+    dependency_provider.add_dependencies("c", 1u32, []);
+    dependency_provider.add_dependencies("c", 2u32, []);
+
+    // x 2 and a 2 conflict, which is only found after deciding on both,
+    // and the search restarts right after backtracking from that conflict.
+    let options = ResolveOptions {
+        restarts: Some(RestartPolicy::Luby { unit: 1 }),
+        ..ResolveOptions::default()
+    };
+    let mut solver = Solver::new("root", 0u32).with_options(options);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("x"), Some(&2));
+    assert_eq!(solution.get("a"), Some(&1));
+    let stats = solver.stats();
+    assert!(stats.restarts >= 1);
+    assert!(stats.backtracks > stats.restarts);
+}
+
+/// Records the last statistics given to `prioritize` for each package.
+struct StatisticsRecordingProvider {
+    dp: OfflineDependencyProvider<&'static str, NumVS>,
+    statistics: RefCell<Map<&'static str, PackageResolutionStatistics>>,
+}
+
+impl DependencyProvider for StatisticsRecordingProvider {
+    type P = &'static str;
+    type V = u32;
+    type VS = NumVS;
+    type M = String;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.statistics
+            .borrow_mut()
+            .insert(package, *package_statistics);
+        self.dp.prioritize(package, range, package_statistics)
+    }
+    type Priority =
+        <OfflineDependencyProvider<&'static str, NumVS> as DependencyProvider>::Priority;
+
+    type Err = Infallible;
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.dp.choose_version(package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        self.dp.get_dependencies(package, version)
+    }
+}
+
+#[test]
+fn conflict_statistics_given_to_prioritize() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("x", Ranges::full()), ("a", Ranges::full())]);
+    dp.add_dependencies("x", 1u32, []);
+    dp.add_dependencies("x", 2u32, [("c", Ranges::singleton(1u32))]);
+    dp.add_dependencies("a", 1u32, []);
+    dp.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dp.add_dependencies("b", 1u32, [("c", Ranges::singleton(2u32))]);
+    dp.add_dependencies("c", 1u32, []);
+    dp.add_dependencies("c", 2u32, []);
+    let provider = StatisticsRecordingProvider {
+        dp,
+        statistics: RefCell::default(),
+    };
+    let solution = resolve(&provider, "root", 0u32).unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+
+    // The conflict between the dependencies of x 2 and a 2 is on c,
+    // and the packages are prioritized again after backtracking from it.
+    let statistics = provider.statistics.into_inner();
+    assert!(statistics["a"].conflict_count() >= 1);
+    assert!(statistics["c"].unit_propagation_culprit >= 1);
+    assert_eq!(statistics["root"], PackageResolutionStatistics::default());
+}
+
+fn conflicting_a2_registry() -> OfflineDependencyProvider<&'static str, NumVS> {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root1", 1u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies(
+        "root2",
+        1u32,
+        [("a", Ranges::full()), ("e", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("root3", 1u32, [("a", Ranges::singleton(2u32))]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full()), ("c", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("d", Ranges::singleton(1u32))]);
+    dependency_provider.add_dependencies("c", 1u32, [("d", Ranges::singleton(2u32))]);
+    dependency_provider.add_dependencies("d", 1u32, []);
+    dependency_provider.add_dependencies("d", 2u32, []);
+    dependency_provider.add_dependencies("e", 1u32, []);
+    dependency_provider
+}
+
+#[test]
+fn reuse_learned_incompatibilities() {
+    let dependency_provider = conflicting_a2_registry();
+
+    // Resolving root1 learns that a 2 cannot be selected.
+    let mut solver = Solver::new("root1", 1u32);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+    let learned = solver.learned_incompatibilities();
+    assert!(!learned.is_empty());
+
+    // Without it, root2 goes through the same conflict.
+    let mut solver = Solver::new("root2", 1u32);
+    let expected = solver.solve(&dependency_provider).unwrap();
+    assert!(solver.stats().conflicts > 0);
+
+    let mut solver = Solver::new("root2", 1u32).with_learned_incompatibilities(&learned);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution, expected);
+    assert_eq!(solver.stats().conflicts, 0);
+    assert_eq!(solver.learned_incompatibilities().len(), learned.len());
+
+    // The reused incompatibilities are still explained from the dependencies.
+    let mut solver = Solver::new("root3", 1u32).with_learned_incompatibilities(&learned);
+    let Err(PubGrubError::NoSolution(derivation)) = solver.solve(&dependency_provider) else {
+        panic!("root3 has no solution");
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("b 1 depends on d 1"), "{report}");
+    assert!(report.contains("c 1 depends on d 2"), "{report}");
+}
+
+#[test]
+fn learned_incompatibilities_do_not_depend_on_the_request() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        1u32,
+        [("x", Ranges::full()), ("y", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("x", 1u32, []);
+    dependency_provider.add_dependencies("x", 2u32, []);
+    dependency_provider.add_dependencies("y", 1u32, []);
+    dependency_provider.add_dependencies("y", 2u32, []);
+
+    // Once some solutions are excluded, what the resolution learns from them
+    // is not true for other resolutions.
+    let mut solver = Solver::new("root", 1u32);
+    for _ in 0..2 {
+        solver.solve(&dependency_provider).unwrap();
+        solver.exclude_solution();
+    }
+    solver.solve(&dependency_provider).unwrap();
+    assert!(solver.stats().learned_incompatibilities > 0);
+    assert!(solver.learned_incompatibilities().is_empty());
+}
+
+#[test]
+fn partial_solution_on_failure() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("b", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 2u32, [("c", Ranges::full())]);
+
+    let options = ResolveOptions {
+        partial_solution_on_failure: true,
+        ..ResolveOptions::default()
+    };
+    // a is decided on before b, whose versions all lead to conflicts.
+    let result = resolve_with_options(&dependency_provider, "root", 0u32, options);
+    let Err(PubGrubError::NoSolutionWithPartial(partial)) = result else {
+        panic!("expected a partial solution, got {result:?}");
+    };
+    let PartialResolution {
+        derivation_tree,
+        partial_solution,
+        involved_packages,
+    } = *partial;
+    let expected: Map<_, _> = [("root", 0), ("a", 1)].into_iter().collect();
+    assert_eq!(partial_solution, expected);
+    assert_eq!(
+        involved_packages,
+        derivation_tree.packages().into_iter().cloned().collect()
+    );
+    assert!(involved_packages.contains("c"));
+    assert!(!involved_packages.contains("a"));
+
+    let result = resolve_with_options(
+        &dependency_provider,
+        "root",
+        0u32,
+        ResolveOptions::default(),
+    );
+    assert!(matches!(result, Err(PubGrubError::NoSolution(_))));
+}
+
+#[test]
+fn upgrade_only_unlocked_packages() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("b", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("a", 1u32, [("c", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 2u32, [("c", Ranges::higher_than(2u32))]);
+    dependency_provider.add_dependencies("b", 1u32, [("c", Ranges::strictly_lower_than(2u32))]);
+    dependency_provider.add_dependencies("b", 2u32, [("c", Ranges::full())]);
+    dependency_provider.add_dependencies("c", 1u32, []);
+    dependency_provider.add_dependencies("c", 2u32, []);
+    let locked: Map<_, _> = [("root", 0), ("a", 1), ("b", 1), ("c", 1)]
+        .into_iter()
+        .collect();
+
+    let blocked = |upgrade: &Upgrade<_, _, _>| -> Vec<_> {
+        upgrade
+            .blocked
+            .iter()
+            .map(|blocked| (blocked.package, blocked.version, blocked.locked.clone()))
+            .collect()
+    };
+
+    // Upgrading a needs c 2, which b 1 does not allow.
+    let upgrade =
+        resolve_upgrade(&dependency_provider, "root", 0u32, &locked, ["a"], false).unwrap();
+    assert_eq!(upgrade.solution, locked);
+    let mut blocked_by = blocked(&upgrade);
+    blocked_by[0].2.sort();
+    assert_eq!(blocked_by, [("a", 2, vec!["b", "c"])]);
+    let report = DefaultStringReporter::report(&upgrade.blocked[0].reason);
+    assert_eq!(report, "a 2 depends on c >=2");
+
+    // c is a dependency of a, so only b is left to unlock.
+    let upgrade =
+        resolve_upgrade(&dependency_provider, "root", 0u32, &locked, ["a"], true).unwrap();
+    assert_eq!(upgrade.solution, locked);
+    assert_eq!(blocked(&upgrade), [("a", 2, vec!["b"])]);
+
+    let upgrade = resolve_upgrade(
+        &dependency_provider,
+        "root",
+        0u32,
+        &locked,
+        ["a", "b"],
+        true,
+    )
+    .unwrap();
+    let expected: Map<_, _> = [("root", 0), ("a", 2), ("b", 2), ("c", 2)]
+        .into_iter()
+        .collect();
+    assert_eq!(upgrade.solution, expected);
+    assert!(upgrade.blocked.is_empty());
+
+    // Locked versions are never given up.
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("c", Ranges::higher_than(2u32))],
+    );
+    let Err(PubGrubError::NoSolution(derivation)) =
+        resolve_upgrade(&dependency_provider, "root", 0u32, &locked, ["a"], false)
+    else {
+        panic!("c is locked to 1");
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("c is locked to 1"), "{report}");
+}
+
+/// Counts the events of a resolution.
+#[derive(Default)]
+struct CountingObserver {
+    decisions: RefCell<Vec<(&'static str, u32)>>,
+    derivations: RefCell<u64>,
+    conflicts: RefCell<u64>,
+    learned: RefCell<u64>,
+    backtracks: RefCell<Vec<u32>>,
+}
+
+impl Observer<&'static str, NumVS> for CountingObserver {
+    fn decision(&self, package: &&'static str, version: &u32) {
+        self.decisions.borrow_mut().push((package, *version));
+    }
+
+    fn derivation(&self, _package: &&'static str, _term: &Term<NumVS>) {
+        *self.derivations.borrow_mut() += 1;
+    }
+
+    fn conflict(&self, incompatibility: IncompatibilityTerms<'_, &'static str, NumVS>) {
+        assert!(!incompatibility.is_empty());
+        assert_eq!(incompatibility.iter().count(), incompatibility.len());
+        *self.conflicts.borrow_mut() += 1;
+    }
+
+    fn learned(&self, _incompatibility: IncompatibilityTerms<'_, &'static str, NumVS>) {
+        *self.learned.borrow_mut() += 1;
+    }
+
+    fn backtrack(&self, decision_level: u32) {
+        self.backtracks.borrow_mut().push(decision_level);
+    }
+}
+
+#[test]
+fn observer_sees_every_step() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+
+    let observer = CountingObserver::default();
+    let mut solver = Solver::<OfflineDependencyProvider<_, NumVS>>::new("root", 0u32);
+    solver
+        .solve_with_observer(&dependency_provider, &observer)
+        .unwrap();
+    let stats = solver.stats();
+
+    assert_eq!(
+        observer.decisions.into_inner(),
+        vec![("root", 0), ("a", 2), ("b", 1), ("a", 1)]
+    );
+    assert_eq!(observer.derivations.into_inner(), stats.unit_propagations);
+    assert_eq!(observer.conflicts.into_inner(), stats.conflicts);
+    assert_eq!(
+        observer.learned.into_inner(),
+        stats.learned_incompatibilities
+    );
+    // b 1 is ruled out first, then a 2 along with it.
+    let backtracks = observer.backtracks.into_inner();
+    assert_eq!(backtracks, vec![2, 1]);
+    assert_eq!(backtracks.len() as u64, stats.backtracks);
+}
+
+/// Adds other kinds of dependencies to the requirements of an offline provider.
+struct ExtendedDependencyProvider {
+    dp: OfflineDependencyProvider<&'static str, NumVS>,
+    extra: Map<(&'static str, u32), Vec<Dependency<&'static str, NumVS>>>,
+}
+
+impl DependencyProvider for ExtendedDependencyProvider {
+    type P = &'static str;
+    type V = u32;
+    type VS = NumVS;
+    type M = String;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.dp.prioritize(package, range, package_statistics)
+    }
+    type Priority =
+        <OfflineDependencyProvider<&'static str, NumVS> as DependencyProvider>::Priority;
+
+    type Err = Infallible;
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.dp.choose_version(package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let Dependencies::Available(requirements) = self.dp.get_dependencies(package, version)?
+        else {
+            return self.dp.get_dependencies(package, version);
+        };
+        let extra = self
+            .extra
+            .get(&(*package, *version))
+            .into_iter()
+            .flatten()
+            .cloned();
+        Ok(Dependencies::Extended(
+            requirements
+                .into_iter()
+                .map(|(p, range)| Dependency::Requires(p, range))
+                .chain(extra)
+                .collect(),
+        ))
+    }
+}
+
+#[test]
+fn constraints_only_apply_to_selected_packages() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dp.add_dependencies("a", 1u32, []);
+    dp.add_dependencies("a", 2u32, []);
+    dp.add_dependencies("b", 1u32, []);
+    dp.add_dependencies("c", 1u32, [("b", Ranges::singleton(1u32))]);
+    let mut dependency_provider = ExtendedDependencyProvider {
+        dp,
+        extra: Map::default(),
+    };
+    // a 2 conflicts with b < 2.
+    dependency_provider.extra.insert(
+        ("a", 2),
+        vec![Dependency::Constrains("b", Ranges::higher_than(2u32))],
+    );
+
+    // Without b, the constraint does not pull it in.
+    let solution = resolve(&dependency_provider, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("a", 2)].into_iter().collect();
+    assert_eq!(solution, expected);
+
+    // With b 1, a 2 is not usable anymore.
+    dependency_provider.dp.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("c", Ranges::full())],
+    );
+    let solution = resolve(&dependency_provider, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("a", 1), ("b", 1), ("c", 1)]
+        .into_iter()
+        .collect();
+    assert_eq!(solution, expected);
+
+    // Requiring a 2 with b 1 is impossible, and the report explains why.
+    dependency_provider.dp.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::singleton(2u32)), ("c", Ranges::full())],
+    );
+    let Err(PubGrubError::NoSolution(derivation)) = resolve(&dependency_provider, "root", 0u32)
+    else {
+        panic!("a 2 and b 1 are not compatible")
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("a 2 conflicts with b <2"), "{report}");
+    // Derived incompatibilities between two selected packages keep the generic wording.
+    assert!(report.contains("a 2, c 1 are incompatible"), "{report}");
+}
+
+#[test]
+fn any_of_dependencies_try_alternatives_in_order() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, []);
+    dp.add_dependencies("b", 1u32, []);
+    dp.add_dependencies("c", 1u32, []);
+    let mut dependency_provider = ExtendedDependencyProvider {
+        dp,
+        extra: Map::default(),
+    };
+    dependency_provider.extra.insert(
+        ("root", 0),
+        vec![Dependency::AnyOf(vec![
+            ("b", Ranges::full()),
+            ("c", Ranges::full()),
+        ])],
+    );
+
+    // The first alternative is preferred.
+    let solution = resolve(&dependency_provider, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("b", 1)].into_iter().collect();
+    assert_eq!(solution, expected);
+
+    // Once it is not possible, the next one is used.
+    dependency_provider
+        .dp
+        .add_dependencies("b", 1u32, [("d", Ranges::full())]);
+    let solution = resolve(&dependency_provider, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("c", 1)].into_iter().collect();
+    assert_eq!(solution, expected);
+
+    // Without any possible alternative, the report lists them.
+    dependency_provider
+        .dp
+        .add_dependencies("c", 1u32, [("d", Ranges::full())]);
+    let Err(PubGrubError::NoSolution(derivation)) = resolve(&dependency_provider, "root", 0u32)
+    else {
+        panic!("neither b nor c can be selected")
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("root 0 depends on b or c"), "{report}");
+}
+
+#[test]
+fn dependency_graph_of_the_solution() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("a", Ranges::full()), ("c", Ranges::full())]);
+    dp.add_dependencies("a", 1u32, [("c", Ranges::higher_than(1u32))]);
+    dp.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dp.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+    dp.add_dependencies("c", 1u32, []);
+    dp.add_dependencies("c", 2u32, []);
+
+    // a 2 and its dependency on b are backtracked, so that edge is not part of the graph.
+    let graph = resolve_with_graph(&dp, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("a", 1), ("c", 2)].into_iter().collect();
+    assert_eq!(graph.solution, expected);
+    let mut edges = graph.edges;
+    edges.sort_by_key(|edge| (edge.dependent, edge.dependency));
+    let expected = vec![
+        DependencyEdge {
+            dependent: "a",
+            dependency: "c",
+            range: Ranges::higher_than(1u32),
+        },
+        DependencyEdge {
+            dependent: "root",
+            dependency: "a",
+            range: Ranges::full(),
+        },
+        DependencyEdge {
+            dependent: "root",
+            dependency: "c",
+            range: Ranges::full(),
+        },
+    ];
+    assert_eq!(edges, expected);
+
+    // A dependency on any of several packages leads to the selected one.
+    let mut dependency_provider = ExtendedDependencyProvider {
+        dp,
+        extra: Map::default(),
+    };
+    dependency_provider.extra.insert(
+        ("c", 2),
+        vec![Dependency::AnyOf(vec![
+            ("d", Ranges::full()),
+            ("e", Ranges::full()),
+        ])],
+    );
+    dependency_provider.dp.add_dependencies("e", 1u32, []);
+    let graph = resolve_with_graph(&dependency_provider, "root", 0u32).unwrap();
+    assert_eq!(graph.solution.get("e"), Some(&1));
+    assert!(graph.edges.contains(&DependencyEdge {
+        dependent: "c",
+        dependency: "e",
+        range: Ranges::full(),
+    }));
+    assert_eq!(graph.edges.len(), 4);
+}
+
+#[test]
+fn explain_why_packages_are_selected() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 1u32, [("c", Ranges::higher_than(1u32))]);
+    dependency_provider.add_dependencies("a", 2u32, [("b", Ranges::full())]);
+    dependency_provider.add_dependencies("b", 1u32, [("a", Ranges::singleton(1u32))]);
+    dependency_provider.add_dependencies("c", 1u32, []);
+    dependency_provider.add_dependencies("c", 2u32, []);
+
+    let mut solver = Solver::new("root", 0u32);
+    solver.solve(&dependency_provider).unwrap();
+    let reason = solver.why_selected(&"c").unwrap();
+    assert!(matches!(
+        reason.steps.last(),
+        Some(SelectionStep::Decision {
+            package: "c",
+            version: 2
+        })
+    ));
+    let report = reason.report();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "Because we are solving dependencies of root 0, root 0 is required",
+            "root 0 is selected",
+            "Because root 0 depends on a, a * is required",
+            "Because there is no version of b in <1 | >1 and b 1 depends on a 1, b depends on a 1.",
+            "And because a 2 depends on b, a 2 is forbidden.",
+            "a 1 is selected",
+            "Because a 1 depends on c >=1, c >=1 is required",
+            "c 2 is selected",
+        ]
+    );
+
+    // Backtracked packages are not part of the solution.
+    assert!(solver.why_selected(&"b").is_none());
+}
+
+#[test]
+fn explain_why_newer_versions_are_not_selected() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [("a", Ranges::full()), ("b", Ranges::full())],
+    );
+    dependency_provider.add_dependencies("a", 1u32, []);
+    dependency_provider.add_dependencies("a", 2u32, []);
+    dependency_provider.add_dependencies("a", 3u32, [("c", Ranges::full())]);
+    dependency_provider.add_dependencies("a", 4u32, []);
+    dependency_provider.add_dependencies("b", 1u32, [("a", Ranges::strictly_lower_than(4u32))]);
+
+    let reports = |solver: &Solver<_>| -> Vec<_> {
+        let versions = dependency_provider.versions(&"a").unwrap().cloned();
+        solver
+            .why_not_newer(&"a", versions)
+            .unwrap()
+            .into_iter()
+            .map(|newer| {
+                let report = newer
+                    .reason
+                    .map(|tree| DefaultStringReporter::report(&tree));
+                (newer.version, report)
+            })
+            .collect()
+    };
+
+    let mut solver = Solver::new("root", 0u32);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("a"), Some(&2));
+    assert_eq!(
+        reports(&solver),
+        [
+            (
+                3,
+                Some(
+                    "Because there is no available version for c and a 3 depends on c, a 3 is forbidden."
+                        .to_string()
+                )
+            ),
+            (4, Some("b 1 depends on a <4".to_string())),
+        ]
+    );
+
+    // Nothing excludes a newer version that the preferences skipped.
+    let preferences = [("a", 1)].into_iter().collect();
+    let mut solver = Solver::new("root", 0u32).with_preferences(preferences);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    assert_eq!(solution.get("a"), Some(&1));
+    let reports = reports(&solver);
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports[0], (2, None));
+
+    assert!(solver.why_not_newer(&"c", []).is_none());
+}
+
+#[test]
+fn virtual_packages_are_satisfied_by_their_providers() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("mta", Ranges::full())]);
+    dp.add_dependencies("mta", 0u32, []);
+    dp.add_dependencies("postfix", 3u32, [("libfoo", Ranges::full())]);
+    dp.add_dependencies("exim", 4u32, []);
+    let mut dependency_provider = ExtendedDependencyProvider {
+        dp,
+        extra: Map::default(),
+    };
+    dependency_provider.extra.insert(
+        ("mta", 0),
+        vec![Dependency::AnyOf(vec![
+            ("postfix", Ranges::full()),
+            ("exim", Ranges::full()),
+        ])],
+    );
+    dependency_provider
+        .extra
+        .insert(("postfix", 3), vec![Dependency::Provides("mta", 0)]);
+    dependency_provider
+        .extra
+        .insert(("exim", 4), vec![Dependency::Provides("mta", 0)]);
+
+    // postfix cannot be installed without libfoo, so exim provides the mta and stands in for it.
+    let mut solver = Solver::new("root", 0u32);
+    let solution = solver.solve(&dependency_provider).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("exim", 4)].into_iter().collect();
+    assert_eq!(solution, expected);
+    let expected: Map<_, _> = [("mta", ("exim", 4))].into_iter().collect();
+    assert_eq!(solver.providers(), expected);
+    let graph = resolve_with_graph(&dependency_provider, "root", 0u32).unwrap();
+    assert_eq!(graph.solution, solution);
+    assert_eq!(
+        graph.edges,
+        [DependencyEdge {
+            dependent: "root",
+            dependency: "exim",
+            range: Ranges::singleton(4u32),
+        }]
+    );
+
+    // A virtual package is not asked for once its provider is selected.
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("exim", Ranges::full())]);
+    dp.add_dependencies("exim", 4u32, []);
+    let mut dependency_provider = ExtendedDependencyProvider {
+        dp,
+        extra: Map::default(),
+    };
+    dependency_provider
+        .extra
+        .insert(("exim", 4), vec![Dependency::Provides("mta", 0)]);
+    let solution = resolve(&dependency_provider, "root", 0u32).unwrap();
+    let expected: Map<_, _> = [("root", 0), ("exim", 4)].into_iter().collect();
+    assert_eq!(solution, expected);
+
+    // A provider conflicts with the requirements on other versions of the virtual package.
+    dependency_provider.dp.add_dependencies(
+        "root",
+        0u32,
+        [("exim", Ranges::full()), ("mta", Ranges::higher_than(1u32))],
+    );
+    dependency_provider.dp.add_dependencies("mta", 1u32, []);
+    let Err(PubGrubError::NoSolution(derivation)) = resolve(&dependency_provider, "root", 0u32)
+    else {
+        panic!("exim does not provide mta 1")
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("exim 4 provides mta 0"), "{report}");
+}
+
+/// Buckets versions by hundreds, like major versions.
+struct Majors;
+
+impl Buckets<&'static str, NumVS> for Majors {
+    type Bucket = u32;
+
+    fn bucket(&self, _package: &&'static str, version: &u32) -> u32 {
+        version / 100
+    }
+
+    fn split(&self, _package: &&'static str, versions: &NumVS) -> Vec<(u32, NumVS)> {
+        (0..10)
+            .map(|major| {
+                let bucket = Ranges::between(major * 100, (major + 1) * 100);
+                (major, versions.intersection(&bucket))
+            })
+            .filter(|(_, versions)| versions != &Ranges::empty())
+            .collect()
+    }
+}
+
+#[test]
+fn buckets_allow_incompatible_versions_together() {
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies(
+        "root",
+        0u32,
+        [
+            ("a", Ranges::between(100u32, 200u32)),
+            ("b", Ranges::full()),
+        ],
+    );
+    dependency_provider.add_dependencies("a", 101u32, []);
+    dependency_provider.add_dependencies("a", 201u32, []);
+    dependency_provider.add_dependencies("b", 100u32, [("a", Ranges::between(200u32, 300u32))]);
+    assert!(resolve(&dependency_provider, "root", 0u32).is_err());
+
+    let bucketed = Bucketed::new(dependency_provider, Majors);
+    let root = bucketed.package("root", &0);
+    let solution = resolve(&bucketed, root, 0u32).unwrap();
+    let versions =
+        Bucketed::<OfflineDependencyProvider<_, NumVS>, Majors>::versions_by_package(&solution);
+    let expected: Map<_, _> = [
+        ("root", vec![(0, 0)]),
+        ("a", vec![(1, 101), (2, 201)]),
+        ("b", vec![(1, 100)]),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(versions, expected);
+
+    // Reports refer to the buckets.
+    let mut dependency_provider = OfflineDependencyProvider::<_, NumVS>::new();
+    dependency_provider.add_dependencies("root", 0u32, [("a", Ranges::between(300u32, 400u32))]);
+    let bucketed = Bucketed::new(dependency_provider, Majors);
+    let root = bucketed.package("root", &0);
+    let Err(PubGrubError::NoSolution(derivation)) = resolve(&bucketed, root, 0u32) else {
+        panic!("there is no a 3xx")
+    };
+    let report = DefaultStringReporter::report(&derivation);
+    assert!(report.contains("root@0 0 depends on a@3"), "{report}");
+}
+
+/// A dependency that only applies on one operating system: the system, package and range.
+type PlatformDependency = (&'static str, &'static str, NumVS);
+
+/// Adds dependencies that only apply on some operating systems to an offline provider.
+struct PlatformDependencyProvider {
+    dp: OfflineDependencyProvider<&'static str, NumVS>,
+    platform_specific: Map<(&'static str, u32), Vec<PlatformDependency>>,
+}
+
+impl DependencyProvider for PlatformDependencyProvider {
+    type P = &'static str;
+    type V = u32;
+    type VS = NumVS;
+    type M = String;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_statistics: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.dp.prioritize(package, range, package_statistics)
+    }
+    type Priority =
+        <OfflineDependencyProvider<&'static str, NumVS> as DependencyProvider>::Priority;
+
+    type Err = Infallible;
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.dp.choose_version(package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        self.dp.get_dependencies(package, version)
+    }
+}
+
+impl EnvironmentDependencyProvider for PlatformDependencyProvider {
+    type Environment = &'static str;
+
+    fn get_environment_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+        environment: &Self::Environment,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let Dependencies::Available(mut requirements) =
+            self.dp.get_dependencies(package, version)?
+        else {
+            return self.dp.get_dependencies(package, version);
+        };
+        let platform_specific = self.platform_specific.get(&(*package, *version));
+        for (platform, p, range) in platform_specific.into_iter().flatten() {
+            if platform == environment {
+                requirements.insert(p, range.clone());
+            }
+        }
+        Ok(Dependencies::Available(requirements))
+    }
+}
+
+#[test]
+fn universal_resolution_forks_per_environment() {
+    let mut dp = OfflineDependencyProvider::<_, NumVS>::new();
+    dp.add_dependencies("root", 0u32, [("a", Ranges::full())]);
+    dp.add_dependencies("a", 1u32, []);
+    dp.add_dependencies("c", 1u32, []);
+    dp.add_dependencies("c", 2u32, []);
+    dp.add_dependencies("win32", 1u32, []);
+    let platform_specific = [
+        (("root", 0), vec![("windows", "win32", Ranges::full())]),
+        (
+            ("a", 1),
+            vec![
+                ("windows", "c", Ranges::strictly_lower_than(2u32)),
+                ("linux", "c", Ranges::higher_than(2u32)),
+                ("macos", "c", Ranges::higher_than(2u32)),
+            ],
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let dp = PlatformDependencyProvider {
+        dp,
+        platform_specific,
+    };
+
+    let universal = resolve_universal(&dp, "root", 0u32, ["linux", "macos", "windows"]).unwrap();
+    assert_eq!(universal.forks.len(), 2);
+    let unix: Map<_, _> = [("root", 0), ("a", 1), ("c", 2)].into_iter().collect();
+    let windows: Map<_, _> = [("root", 0), ("a", 1), ("c", 1), ("win32", 1)]
+        .into_iter()
+        .collect();
+    assert_eq!(universal.solution(&"linux"), Some(&unix));
+    assert_eq!(universal.solution(&"macos"), Some(&unix));
+    assert_eq!(universal.solution(&"windows"), Some(&windows));
+    assert_eq!(universal.solution(&"freebsd"), None);
+
+    let lock = universal.lock();
+    assert_eq!(lock[&"a"], vec![(1, vec!["linux", "macos", "windows"])]);
+    let mut c = lock[&"c"].clone();
+    c.sort();
+    assert_eq!(c, vec![(1, vec!["windows"]), (2, vec!["linux", "macos"])]);
+    assert_eq!(lock[&"win32"], vec![(1, vec!["windows"])]);
+
+    // Every environment must have a solution.
+    let mut dp = dp;
+    dp.platform_specific
+        .get_mut(&("root", 0))
+        .unwrap()
+        .push(("linux", "missing", Ranges::full()));
+    assert!(matches!(
+        resolve_universal(&dp, "root", 0u32, ["linux", "windows"]),
+        Err(PubGrubError::NoSolution(_))
+    ));
+}